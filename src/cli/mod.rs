@@ -1,4 +1,6 @@
-use clap::{Parser, Subcommand};
+use crate::config::EmbeddingDtype;
+use crate::storage::{SearchFilter, SearchMode, SourceType};
+use clap::{Args, Parser, Subcommand};
 use std::path::PathBuf;
 
 #[derive(Parser)]
@@ -25,11 +27,37 @@ pub enum Commands {
         limit: usize,
         #[arg(short, long)]
         json: bool,
+        #[arg(short, long, default_value = "vector")]
+        mode: SearchMode,
+        /// Drop results with similarity below this (0-1, normalized per query for fts and hybrid)
+        #[arg(long)]
+        min_score: Option<f32>,
+        /// IVF partitions to probe (default: storage.index.nprobes)
+        #[arg(long)]
+        nprobes: Option<usize>,
+        /// Re-rank this many times `limit` candidates with exact distances
+        #[arg(long)]
+        refine_factor: Option<u32>,
+        #[command(flatten)]
+        filter: FilterArgs,
     },
     Search {
         query: String,
         #[arg(short, long, default_value = "10")]
         limit: usize,
+        #[arg(short, long, default_value = "vector")]
+        mode: SearchMode,
+        /// Drop results with similarity below this (0-1, normalized per query for fts and hybrid)
+        #[arg(long)]
+        min_score: Option<f32>,
+        /// IVF partitions to probe (default: storage.index.nprobes)
+        #[arg(long)]
+        nprobes: Option<usize>,
+        /// Re-rank this many times `limit` candidates with exact distances
+        #[arg(long)]
+        refine_factor: Option<u32>,
+        #[command(flatten)]
+        filter: FilterArgs,
     },
     Serve {
         #[arg(short, long, default_value = "127.0.0.1")]
@@ -44,9 +72,85 @@ pub enum Commands {
     Delete {
         source: String,
     },
+    Storage {
+        #[command(subcommand)]
+        action: StorageCommands,
+    },
+    /// Re-embed the whole index with another model into a new table
+    MigrateEmbeddings {
+        /// Hugging Face model id to re-embed with
+        #[arg(long, required_unless_present = "adopt_legacy")]
+        model: Option<String>,
+        /// Record that a table without a fingerprint was built with this model
+        /// (mean pooling, no prefix), instead of migrating
+        #[arg(long, value_name = "MODEL", conflicts_with_all = ["model", "table"])]
+        adopt_legacy: Option<String>,
+        /// Target table (default: <table_name>_<model>)
+        #[arg(long)]
+        table: Option<String>,
+        #[arg(long, default_value_t = 256)]
+        batch_size: usize,
+    },
+    Bench {
+        #[command(subcommand)]
+        action: BenchCommands,
+    },
+    Cache {
+        #[command(subcommand)]
+        action: CacheCommands,
+    },
     Status,
 }
 
+#[derive(Args)]
+pub struct FilterArgs {
+    /// Only search these sources (e.g. github:launchbadge/sqlx)
+    #[arg(long = "source")]
+    pub sources: Vec<String>,
+    /// Exclude these sources
+    #[arg(long = "exclude-source")]
+    pub exclude_sources: Vec<String>,
+    #[arg(long)]
+    pub source_type: Option<SourceType>,
+    /// File path prefix, or a glob if it contains `*` or `?`
+    #[arg(long)]
+    pub path: Option<String>,
+    /// File extension (e.g. rs)
+    #[arg(long = "ext")]
+    pub extensions: Vec<String>,
+    /// Language name (e.g. rust, python)
+    #[arg(long = "lang")]
+    pub languages: Vec<String>,
+    /// Only chunks indexed at or after this time (RFC 3339 or YYYY-MM-DD)
+    #[arg(long)]
+    pub since: Option<String>,
+    /// Only chunks indexed before this time (RFC 3339 or YYYY-MM-DD)
+    #[arg(long)]
+    pub until: Option<String>,
+}
+
+impl From<FilterArgs> for SearchFilter {
+    fn from(args: FilterArgs) -> Self {
+        let (path_prefix, path_glob) = match args.path {
+            Some(path) if path.contains(['*', '?']) => (None, Some(path)),
+            Some(path) => (Some(path), None),
+            None => (None, None),
+        };
+
+        SearchFilter {
+            sources: args.sources,
+            exclude_sources: args.exclude_sources,
+            source_type: args.source_type,
+            path_prefix,
+            path_glob,
+            extensions: args.extensions,
+            languages: args.languages,
+            created_after: args.since,
+            created_before: args.until,
+        }
+    }
+}
+
 #[derive(Subcommand)]
 pub enum IndexCommands {
     Github {
@@ -59,6 +163,12 @@ pub enum IndexCommands {
         path: PathBuf,
     },
     Defaults,
+    /// Update the vector and full-text indices after large ingests
+    Optimize {
+        /// Retrain the vector index from scratch
+        #[arg(long)]
+        rebuild: bool,
+    },
 }
 
 #[derive(Subcommand)]
@@ -75,3 +185,51 @@ pub enum UpdateCommands {
     },
     Status,
 }
+
+#[derive(Subcommand)]
+pub enum StorageCommands {
+    /// Merge small fragments into larger ones
+    Compact,
+    /// Delete old table versions
+    Cleanup {
+        /// Keep versions newer than this (e.g. 12h, 7d, 2w)
+        #[arg(long, default_value = "7d")]
+        older_than: String,
+    },
+    /// Show fragment count, on-disk size, rows per source and index coverage
+    Stats {
+        #[arg(short, long)]
+        json: bool,
+    },
+    /// List table versions and their tags
+    Versions {
+        #[arg(short, long)]
+        json: bool,
+    },
+    /// Restore a previous table version as the latest one
+    Rollback {
+        /// Version number or tag
+        target: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum BenchCommands {
+    /// Measure embedding throughput and memory for each dtype
+    Embed {
+        /// File or directory to chunk as the sample corpus
+        #[arg(long, default_value = ".")]
+        corpus: PathBuf,
+        /// Number of chunks to embed per setting
+        #[arg(long, default_value_t = 1000)]
+        chunks: usize,
+        #[arg(long, value_delimiter = ',', default_value = "f32,f16,bf16")]
+        dtypes: Vec<EmbeddingDtype>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommands {
+    /// Delete all cached embeddings
+    Clear,
+}
@@ -21,16 +21,258 @@ pub struct BrainConfig {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EmbeddingConfig {
+    #[serde(default)]
+    pub provider: EmbeddingProvider,
     pub model: String,
+    /// Local directory with config.json, tokenizer.json and model.safetensors.
+    pub model_path: Option<PathBuf>,
+    /// Hub branch, tag or commit to load (default: main).
+    pub revision: Option<String>,
+    /// Only use the Hugging Face cache, as with `HF_HUB_OFFLINE=1`.
+    #[serde(default)]
+    pub offline: bool,
     pub dimensions: usize,
+    /// Truncate vectors to this many leading dimensions (Matryoshka models only).
+    pub output_dimensions: Option<usize>,
     pub max_length: usize,
-    pub cuda_device: usize,
+    /// `"cpu"`, `"cuda"` or `"cuda:N"`; unset means `"cuda:1"` on CUDA builds
+    /// and the CPU otherwise.
+    pub device: Option<String>,
+    /// Deprecated: GPU ordinal used when `device` is unset.
+    pub cuda_device: Option<usize>,
+    /// Weight precision; half precision needs a GPU.
+    #[serde(default)]
+    pub dtype: EmbeddingDtype,
+    /// What to do with chunks longer than `max_length` tokens.
+    #[serde(default)]
+    pub overflow: OverflowMode,
+    /// Chunks per forward pass.
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    /// Defaults to what the model family was trained with.
+    pub pooling: Option<Pooling>,
+    #[serde(default = "default_normalize")]
+    pub normalize: bool,
+    /// Prepended to search queries; defaults to the model family's instruction.
+    pub query_prefix: Option<String>,
+    /// Prepended to indexed chunks; defaults to the model family's prefix.
+    pub document_prefix: Option<String>,
+    #[serde(default)]
+    pub cache: EmbeddingCacheConfig,
+    /// Server for the `openai` provider, e.g. `http://localhost:11434`.
+    pub base_url: Option<String>,
+    pub api_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowMode {
+    /// Embed the first `max_length` tokens; the rest is only found by full-text search.
+    #[default]
+    Truncate,
+    /// Split the chunk into extra chunks that each fit.
+    Split,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbeddingCacheConfig {
+    #[serde(default = "default_cache_enabled")]
+    pub enabled: bool,
+    /// Relative to the data directory.
+    #[serde(default = "default_cache_path")]
+    pub path: PathBuf,
+    #[serde(default = "default_cache_max_size_mb")]
+    pub max_size_mb: u64,
+}
+
+impl Default for EmbeddingCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_cache_enabled(),
+            path: default_cache_path(),
+            max_size_mb: default_cache_max_size_mb(),
+        }
+    }
+}
+
+fn default_cache_enabled() -> bool {
+    true
+}
+
+fn default_cache_path() -> PathBuf {
+    PathBuf::from("embedding_cache")
+}
+
+fn default_cache_max_size_mb() -> u64 {
+    1024
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbeddingDtype {
+    #[default]
+    F32,
+    F16,
+    Bf16,
+}
+
+impl From<EmbeddingDtype> for candle_core::DType {
+    fn from(dtype: EmbeddingDtype) -> Self {
+        match dtype {
+            EmbeddingDtype::F32 => candle_core::DType::F32,
+            EmbeddingDtype::F16 => candle_core::DType::F16,
+            EmbeddingDtype::Bf16 => candle_core::DType::BF16,
+        }
+    }
+}
+
+impl std::fmt::Display for EmbeddingDtype {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EmbeddingDtype::F32 => write!(f, "f32"),
+            EmbeddingDtype::F16 => write!(f, "f16"),
+            EmbeddingDtype::Bf16 => write!(f, "bf16"),
+        }
+    }
+}
+
+impl std::str::FromStr for EmbeddingDtype {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "f32" => Ok(EmbeddingDtype::F32),
+            "f16" => Ok(EmbeddingDtype::F16),
+            "bf16" => Ok(EmbeddingDtype::Bf16),
+            _ => Err(format!("Unknown dtype: {} (expected f32, f16 or bf16)", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmbeddingProvider {
+    /// Local Candle model downloaded from the Hugging Face hub.
+    #[default]
+    Candle,
+    /// OpenAI-compatible `/v1/embeddings` endpoint (mistral.rs, llama.cpp server, Ollama).
+    Openai,
+    /// Deterministic token hashing, for tests.
+    Hash,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Pooling {
+    Cls,
+    Mean,
+    LastToken,
+}
+
+impl std::fmt::Display for Pooling {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Pooling::Cls => write!(f, "cls"),
+            Pooling::Mean => write!(f, "mean"),
+            Pooling::LastToken => write!(f, "last_token"),
+        }
+    }
+}
+
+/// Settings that describe one particular model and do not carry over to another.
+const MODEL_SPECIFIC_KEYS: [&str; 6] = [
+    "model_path",
+    "revision",
+    "output_dimensions",
+    "pooling",
+    "query_prefix",
+    "document_prefix",
+];
+
+impl EmbeddingConfig {
+    /// This config for another model, without the settings of the current one.
+    pub fn for_model(&self, model: &str) -> Self {
+        Self {
+            model: model.to_string(),
+            model_path: None,
+            revision: None,
+            output_dimensions: None,
+            pooling: None,
+            query_prefix: None,
+            document_prefix: None,
+            ..self.clone()
+        }
+    }
+}
+
+fn default_normalize() -> bool {
+    true
+}
+
+fn default_batch_size() -> usize {
+    32
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StorageConfig {
     pub lancedb_path: PathBuf,
     pub table_name: String,
+    #[serde(default)]
+    pub index: VectorIndexConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VectorIndexConfig {
+    #[serde(default)]
+    pub index_type: VectorIndexType,
+    #[serde(default)]
+    pub distance_type: VectorDistance,
+    pub num_partitions: Option<u32>,
+    pub num_sub_vectors: Option<u32>,
+    #[serde(default = "default_nprobes")]
+    pub nprobes: usize,
+    pub refine_factor: Option<u32>,
+    #[serde(default = "default_index_min_rows")]
+    pub min_rows: usize,
+}
+
+impl Default for VectorIndexConfig {
+    fn default() -> Self {
+        Self {
+            index_type: VectorIndexType::default(),
+            distance_type: VectorDistance::default(),
+            num_partitions: None,
+            num_sub_vectors: None,
+            nprobes: default_nprobes(),
+            refine_factor: None,
+            min_rows: default_index_min_rows(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum VectorIndexType {
+    #[default]
+    IvfPq,
+    IvfHnswPq,
+    IvfHnswSq,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum VectorDistance {
+    #[default]
+    Cosine,
+    Dot,
+}
+
+fn default_nprobes() -> usize {
+    20
+}
+
+fn default_index_min_rows() -> usize {
+    10_000
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -72,19 +314,43 @@ pub struct DefaultSource {
 
 impl Config {
     pub fn load() -> anyhow::Result<Self> {
-        let config_path = directories::ProjectDirs::from("com", "local", "brain")
+        let content = std::fs::read_to_string(Self::path())?;
+        let config: Config = toml::from_str(&content)?;
+        Ok(config)
+    }
+
+    /// The config file in use: the user config dir if it has one, else `./config.toml`.
+    pub fn path() -> PathBuf {
+        directories::ProjectDirs::from("com", "local", "brain")
             .map(|dirs| dirs.config_dir().join("config.toml"))
-            .unwrap_or_else(|| PathBuf::from("config.toml"));
-
-        if config_path.exists() {
-            let content = std::fs::read_to_string(&config_path)?;
-            let config: Config = toml::from_str(&content)?;
-            Ok(config)
-        } else {
-            let content = std::fs::read_to_string("config.toml")?;
-            let config: Config = toml::from_str(&content)?;
-            Ok(config)
+            .filter(|path| path.exists())
+            .unwrap_or_else(|| PathBuf::from("config.toml"))
+    }
+
+    /// Points the config file at a new embedding model and table, keeping its
+    /// comments and layout and dropping the old model's settings. The file is replaced atomically.
+    pub fn switch_embedding(
+        model: &str,
+        dimensions: usize,
+        table_name: &str,
+    ) -> anyhow::Result<()> {
+        let path = Self::path();
+        let content = std::fs::read_to_string(&path)?;
+        let mut doc: toml_edit::DocumentMut = content.parse()?;
+
+        doc["embedding"]["model"] = toml_edit::value(model);
+        doc["embedding"]["dimensions"] = toml_edit::value(dimensions as i64);
+        if let Some(embedding) = doc["embedding"].as_table_like_mut() {
+            for key in MODEL_SPECIFIC_KEYS {
+                embedding.remove(key);
+            }
         }
+        doc["storage"]["table_name"] = toml_edit::value(table_name);
+
+        let tmp_path = path.with_extension("toml.tmp");
+        std::fs::write(&tmp_path, doc.to_string())?;
+        std::fs::rename(&tmp_path, &path)?;
+        Ok(())
     }
 
     pub fn data_dir(&self) -> PathBuf {
@@ -0,0 +1,469 @@
+use crate::embedding::{Embedder, ModelFingerprint};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+use tokenizers::Tokenizer;
+
+const STATS_FILE: &str = "stats.json";
+const STATS_LOCK_FILE: &str = "stats.lock";
+/// Lookups counted in memory before they are added to the stats file.
+const FLUSH_EVERY: u64 = 1024;
+
+/// On-disk store of embeddings, one file of little-endian f32s per entry,
+/// evicted least recently used first once it outgrows its size limit.
+pub struct EmbeddingCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    size_bytes: AtomicU64,
+    pending_hits: AtomicU64,
+    pending_misses: AtomicU64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub size_bytes: u64,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// Lifetime lookup counters, shared by every process using the cache.
+#[derive(Default, Serialize, Deserialize)]
+struct Counters {
+    hits: u64,
+    misses: u64,
+}
+
+impl CacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+impl EmbeddingCache {
+    pub fn open(dir: &Path, max_size_mb: u64) -> Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let (_, size_bytes) = entries_size(dir);
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            max_bytes: max_size_mb * 1024 * 1024,
+            size_bytes: AtomicU64::new(size_bytes),
+            pending_hits: AtomicU64::new(0),
+            pending_misses: AtomicU64::new(0),
+        })
+    }
+
+    pub fn stats(&self) -> Result<CacheStats> {
+        let counters = self.read_counters();
+        let (entries, size_bytes) = entries_size(&self.dir);
+        Ok(CacheStats {
+            entries,
+            size_bytes,
+            hits: counters.hits + self.pending_hits.load(Ordering::Relaxed),
+            misses: counters.misses + self.pending_misses.load(Ordering::Relaxed),
+        })
+    }
+
+    /// Deletes every entry and resets the hit counters. Returns the number of entries removed.
+    pub fn clear(&self) -> Result<usize> {
+        let (entries, _) = entries_size(&self.dir);
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                std::fs::remove_dir_all(&path)?;
+            } else {
+                std::fs::remove_file(&path)?;
+            }
+        }
+        self.size_bytes.store(0, Ordering::Relaxed);
+        self.pending_hits.store(0, Ordering::Relaxed);
+        self.pending_misses.store(0, Ordering::Relaxed);
+        Ok(entries)
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(&key[..2]).join(format!("{}.bin", key))
+    }
+
+    fn get(&self, key: &str, dimensions: usize) -> Option<Vec<f32>> {
+        let path = self.entry_path(key);
+        let bytes = std::fs::read(&path).ok()?;
+        if bytes.len() != dimensions * 4 {
+            return None;
+        }
+
+        // Touch the entry so eviction sees it as recently used.
+        if let Ok(file) = std::fs::File::options().append(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+
+        Some(
+            bytes
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect(),
+        )
+    }
+
+    fn put(&self, key: &str, embedding: &[f32]) -> Result<()> {
+        let path = self.entry_path(key);
+        std::fs::create_dir_all(path.parent().expect("entries live in a shard directory"))?;
+
+        let bytes: Vec<u8> = embedding.iter().flat_map(|x| x.to_le_bytes()).collect();
+        let len = bytes.len() as u64;
+        // Concurrent writers of the same key each get their own temporary file.
+        let tmp_path = path.with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+        let previous = std::fs::metadata(&path).map_or(0, |m| m.len());
+        if let Err(e) =
+            std::fs::write(&tmp_path, &bytes).and_then(|()| std::fs::rename(&tmp_path, &path))
+        {
+            let _ = std::fs::remove_file(&tmp_path);
+            return Err(e.into());
+        }
+
+        // An overwritten entry no longer counts towards the size.
+        let resize = |size: u64| size.saturating_sub(previous) + len;
+        let size = self
+            .size_bytes
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |size| {
+                Some(resize(size))
+            })
+            .map_or_else(resize, resize);
+        if size > self.max_bytes {
+            self.evict()?;
+        }
+        Ok(())
+    }
+
+    /// Removes the least recently used entries until the cache is at 90% of its limit.
+    fn evict(&self) -> Result<()> {
+        let mut entries: Vec<(SystemTime, u64, PathBuf)> = walkdir::WalkDir::new(&self.dir)
+            .min_depth(2)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter_map(|e| {
+                let metadata = e.metadata().ok()?;
+                Some((metadata.modified().ok()?, metadata.len(), e.into_path()))
+            })
+            .collect();
+        entries.sort_by_key(|(modified, _, _)| *modified);
+
+        let mut size: u64 = entries.iter().map(|(_, len, _)| len).sum();
+        let target = self.max_bytes / 10 * 9;
+        let mut removed = 0;
+
+        for (_, len, path) in entries {
+            if size <= target {
+                break;
+            }
+            if std::fs::remove_file(&path).is_ok() {
+                size -= len;
+                removed += 1;
+            }
+        }
+
+        self.size_bytes.store(size, Ordering::Relaxed);
+        tracing::info!("Evicted {} embedding cache entries", removed);
+        Ok(())
+    }
+
+    fn read_counters(&self) -> Counters {
+        std::fs::read_to_string(self.dir.join(STATS_FILE))
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    /// Counts lookups in memory and adds them to the stats file every
+    /// [`FLUSH_EVERY`] lookups.
+    fn record(&self, hits: u64, misses: u64) {
+        let pending = self.pending_hits.fetch_add(hits, Ordering::Relaxed)
+            + self.pending_misses.fetch_add(misses, Ordering::Relaxed)
+            + hits
+            + misses;
+        if pending >= FLUSH_EVERY {
+            self.flush_counters();
+        }
+    }
+
+    fn flush_counters(&self) {
+        let hits = self.pending_hits.swap(0, Ordering::Relaxed);
+        let misses = self.pending_misses.swap(0, Ordering::Relaxed);
+        if hits == 0 && misses == 0 {
+            return;
+        }
+        if let Err(e) = self.add_counters(hits, misses) {
+            tracing::warn!("Failed to update embedding cache stats: {}", e);
+        }
+    }
+
+    /// Adds to the stats file under a file lock, so processes sharing the
+    /// cache do not lose each other's counts.
+    fn add_counters(&self, hits: u64, misses: u64) -> Result<()> {
+        let lock = std::fs::File::create(self.dir.join(STATS_LOCK_FILE))?;
+        lock.lock()?;
+
+        let mut counters = self.read_counters();
+        counters.hits += hits;
+        counters.misses += misses;
+        let tmp_path = self
+            .dir
+            .join(format!("{}.{}.tmp", STATS_FILE, uuid::Uuid::new_v4()));
+        std::fs::write(&tmp_path, serde_json::to_string(&counters)?)?;
+        std::fs::rename(&tmp_path, self.dir.join(STATS_FILE))?;
+        Ok(())
+    }
+}
+
+impl Drop for EmbeddingCache {
+    fn drop(&mut self) {
+        self.flush_counters();
+    }
+}
+
+fn entries_size(dir: &Path) -> (usize, u64) {
+    walkdir::WalkDir::new(dir)
+        .min_depth(2)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.metadata().ok())
+        .filter(|m| m.is_file())
+        .fold((0, 0), |(count, size), m| (count + 1, size + m.len()))
+}
+
+/// Serves repeated texts from an [`EmbeddingCache`] and embeds the rest with `inner`.
+pub struct CachedEmbedder {
+    inner: Box<dyn Embedder>,
+    cache: EmbeddingCache,
+    namespace: String,
+}
+
+impl CachedEmbedder {
+    /// `query_prefix` and `max_length` are part of the key because they change
+    /// vectors without being part of the model fingerprint: the prefix of queries,
+    /// the truncation point of long texts.
+    pub fn new(
+        inner: Box<dyn Embedder>,
+        cache: EmbeddingCache,
+        query_prefix: &str,
+        max_length: usize,
+    ) -> Result<Self> {
+        let namespace = format!(
+            "{}\0{}\0{}",
+            serde_json::to_string(&inner.fingerprint())?,
+            query_prefix,
+            max_length
+        );
+        Ok(Self {
+            inner,
+            cache,
+            namespace,
+        })
+    }
+
+    fn key(&self, kind: &str, text: &str) -> String {
+        let normalized = text.replace("\r\n", "\n");
+        let mut hasher = Sha256::new();
+        hasher.update(self.namespace.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(kind.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(normalized.trim().as_bytes());
+        hex::encode(hasher.finalize())
+    }
+}
+
+#[async_trait]
+impl Embedder for CachedEmbedder {
+    async fn embed_documents(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let dimensions = self.inner.dimensions();
+        let keys: Vec<String> = texts.iter().map(|t| self.key("document", t)).collect();
+
+        let mut embeddings: Vec<Option<Vec<f32>>> =
+            keys.iter().map(|k| self.cache.get(k, dimensions)).collect();
+        let hits = embeddings.iter().filter(|e| e.is_some()).count() as u64;
+
+        // Identical texts within a batch are embedded once.
+        let mut pending: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (i, key) in keys.iter().enumerate() {
+            if embeddings[i].is_none() {
+                pending.entry(key.as_str()).or_default().push(i);
+            }
+        }
+        let misses = (texts.len() as u64) - hits;
+
+        if !pending.is_empty() {
+            let pending: Vec<(&str, Vec<usize>)> = pending.into_iter().collect();
+            let miss_texts: Vec<String> = pending
+                .iter()
+                .map(|(_, indices)| texts[indices[0]].clone())
+                .collect();
+            let computed = self.inner.embed_documents(&miss_texts).await?;
+
+            for ((key, indices), embedding) in pending.into_iter().zip(computed) {
+                // A cache that cannot be written only costs speed.
+                if let Err(e) = self.cache.put(key, &embedding) {
+                    tracing::warn!("Failed to write embedding cache entry: {}", e);
+                }
+                for i in indices {
+                    embeddings[i] = Some(embedding.clone());
+                }
+            }
+        }
+
+        self.cache.record(hits, misses);
+
+        Ok(embeddings
+            .into_iter()
+            .map(|e| e.expect("every text is cached or embedded"))
+            .collect())
+    }
+
+    async fn embed_query(&self, query: &str) -> Result<Vec<f32>> {
+        let key = self.key("query", query);
+
+        if let Some(embedding) = self.cache.get(&key, self.inner.dimensions()) {
+            self.cache.record(1, 0);
+            return Ok(embedding);
+        }
+
+        let embedding = self.inner.embed_query(query).await?;
+        if let Err(e) = self.cache.put(&key, &embedding) {
+            tracing::warn!("Failed to write embedding cache entry: {}", e);
+        }
+        self.cache.record(0, 1);
+        Ok(embedding)
+    }
+
+    fn dimensions(&self) -> usize {
+        self.inner.dimensions()
+    }
+
+    fn model_id(&self) -> &str {
+        self.inner.model_id()
+    }
+
+    fn fingerprint(&self) -> ModelFingerprint {
+        self.inner.fingerprint()
+    }
+
+    fn device(&self) -> String {
+        self.inner.device()
+    }
+
+    fn document_tokenizer(&self) -> Option<(Tokenizer, usize)> {
+        self.inner.document_tokenizer()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EmbeddingConfig;
+    use crate::embedding::hash::HashEmbedder;
+
+    fn hash_config(dimensions: usize) -> EmbeddingConfig {
+        toml::from_str(&format!(
+            "provider = \"hash\"\nmodel = \"hash\"\ndimensions = {}\nmax_length = 512",
+            dimensions
+        ))
+        .unwrap()
+    }
+
+    fn cached(
+        dir: &Path,
+        dimensions: usize,
+        query_prefix: &str,
+        max_length: usize,
+    ) -> CachedEmbedder {
+        let inner = Box::new(HashEmbedder::new(&hash_config(dimensions)));
+        let cache = EmbeddingCache::open(dir, 1).unwrap();
+        CachedEmbedder::new(inner, cache, query_prefix, max_length).unwrap()
+    }
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("brain-cache-{}", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn keys_depend_on_model_prefix_and_kind() {
+        let dir = temp_dir();
+        let embedder = cached(&dir, 8, "", 512);
+
+        assert_eq!(
+            embedder.key("document", "a\r\nb \n"),
+            embedder.key("document", "a\nb")
+        );
+        assert_ne!(embedder.key("document", "a"), embedder.key("query", "a"));
+        assert_ne!(
+            embedder.key("query", "a"),
+            cached(&dir, 8, "query: ", 512).key("query", "a")
+        );
+        assert_ne!(
+            embedder.key("document", "a"),
+            cached(&dir, 16, "", 512).key("document", "a")
+        );
+        assert_ne!(
+            embedder.key("document", "a"),
+            cached(&dir, 8, "", 256).key("document", "a")
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn serves_repeated_texts_from_the_cache() {
+        let dir = temp_dir();
+        let embedder = cached(&dir, 8, "", 512);
+        let texts = vec!["alpha".to_string(), "alpha".to_string(), "beta".to_string()];
+
+        let first = embedder.embed_documents(&texts).await.unwrap();
+        let second = embedder.embed_documents(&texts).await.unwrap();
+        assert_eq!(first, second);
+        assert_eq!(first[0], first[1]);
+
+        let stats = embedder.cache.stats().unwrap();
+        assert_eq!((stats.entries, stats.hits, stats.misses), (2, 3, 3));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn overwriting_an_entry_keeps_the_size() {
+        let dir = temp_dir();
+        let cache = EmbeddingCache::open(&dir, 1).unwrap();
+
+        cache.put("abcd", &[1.0; 8]).unwrap();
+        cache.put("abcd", &[2.0; 8]).unwrap();
+        assert_eq!(cache.size_bytes.load(Ordering::Relaxed), 32);
+        assert_eq!(cache.get("abcd", 8), Some(vec![2.0; 8]));
+        assert_eq!(cache.stats().unwrap().entries, 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn counters_are_flushed_when_the_cache_is_dropped() {
+        let dir = temp_dir();
+        let cache = EmbeddingCache::open(&dir, 1).unwrap();
+        cache.record(2, 1);
+        drop(cache);
+
+        let cache = EmbeddingCache::open(&dir, 1).unwrap();
+        cache.record(1, 0);
+        let stats = cache.stats().unwrap();
+        assert_eq!((stats.hits, stats.misses), (3, 1));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
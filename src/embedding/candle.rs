@@ -0,0 +1,325 @@
+use crate::config::{EmbeddingConfig, Pooling};
+use crate::embedding::encoder::Encoder;
+use crate::embedding::files::ModelFiles;
+use crate::embedding::{Embedder, ModelFingerprint, prefixes};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use candle_core::{DType, Device, DeviceLocation, IndexOp, Tensor};
+use tokenizers::{Tokenizer, TruncationParams};
+
+/// Bumped whenever tokenization or the forward pass changes the vectors; 1 is
+/// the first version passing token type ids and the attention mask.
+const ENCODER_VERSION: u32 = 1;
+
+/// Device of CUDA builds when `embedding.device` is unset; GPU 0 is reserved for the system.
+const DEFAULT_CUDA_DEVICE: &str = "cuda:1";
+
+pub struct CandleEmbedder {
+    model: Encoder,
+    tokenizer: Tokenizer,
+    splitter: Tokenizer,
+    device: Device,
+    dtype: DType,
+    dimensions: usize,
+    model_id: String,
+    revision: String,
+    batch_size: usize,
+    pad_id: u32,
+    /// Text tokens that fit next to the document prefix and special tokens.
+    document_budget: usize,
+    pooling: Pooling,
+    normalize: bool,
+    query_prefix: String,
+    document_prefix: String,
+}
+
+impl CandleEmbedder {
+    pub fn new(config: &EmbeddingConfig) -> Result<Self> {
+        let model_id = config.model.as_str();
+        let device = select_device(config)?;
+        tracing::info!("Using device: {}", device_name(&device));
+
+        let files = ModelFiles::resolve(config)?;
+
+        let mut tokenizer = Tokenizer::from_file(&files.tokenizer)
+            .map_err(|e| anyhow::anyhow!("Failed to load tokenizer: {}", e))?;
+        let pad_id = tokenizer.get_padding().map(|p| p.pad_id).unwrap_or(0);
+        // Batches are padded by hand; the untruncated copy sizes chunks.
+        tokenizer.with_padding(None);
+        let splitter = tokenizer.clone();
+
+        tracing::info!("Loading model weights...");
+        let loaded = Encoder::load(
+            &std::fs::read_to_string(&files.config)?,
+            &files.weights,
+            config.dtype.into(),
+            &device,
+        )?;
+        let dimensions = loaded.dimensions;
+        let max_tokens = config.max_length.min(loaded.max_positions);
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: max_tokens,
+                ..Default::default()
+            }))
+            .map_err(|e| anyhow::anyhow!("Failed to configure truncation: {}", e))?;
+        tracing::info!(
+            "Model loaded successfully: {} architecture, {:?}, {} dimensions, {} max tokens",
+            loaded.encoder.name(),
+            loaded.dtype,
+            dimensions,
+            max_tokens
+        );
+
+        let (query_prefix, document_prefix) = prefixes(config);
+
+        // Special tokens and the document prefix share the model's input with the text.
+        let document_overhead = tokenizer
+            .encode(document_prefix.as_str(), true)
+            .map_err(|e| anyhow::anyhow!("Tokenization error: {}", e))?
+            .len();
+
+        Ok(Self {
+            model: loaded.encoder,
+            tokenizer,
+            splitter,
+            device,
+            dtype: loaded.dtype,
+            dimensions,
+            model_id: model_id.to_string(),
+            revision: config
+                .revision
+                .clone()
+                .unwrap_or_else(|| "main".to_string()),
+            batch_size: config.batch_size.max(1),
+            pad_id,
+            document_budget: max_tokens.saturating_sub(document_overhead).max(1),
+            pooling: config.pooling.unwrap_or_else(|| default_pooling(model_id)),
+            normalize: config.normalize,
+            query_prefix,
+            document_prefix,
+        })
+    }
+
+    /// Embeds `texts` in batches of `embedding.batch_size`, returning vectors in input order.
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let encodings = texts
+            .iter()
+            .map(|text| self.encode(text))
+            .collect::<Result<Vec<_>>>()?;
+
+        // Batching texts of similar length keeps padding to a minimum.
+        let mut order: Vec<usize> = (0..encodings.len()).collect();
+        order.sort_by_key(|&i| encodings[i].0.len());
+
+        let mut all_embeddings = vec![Vec::new(); texts.len()];
+
+        for batch in order.chunks(self.batch_size) {
+            let batch_encodings: Vec<_> = batch.iter().map(|&i| &encodings[i]).collect();
+            let embeddings = self.forward(&batch_encodings)?;
+            for (&i, embedding) in batch.iter().zip(embeddings) {
+                all_embeddings[i] = embedding;
+            }
+        }
+
+        Ok(all_embeddings)
+    }
+
+    fn embed_one(&self, text: &str) -> Result<Vec<f32>> {
+        let encoding = self.encode(text)?;
+        Ok(self.forward(&[&encoding])?.remove(0))
+    }
+
+    /// Tokenizes `text`, truncated to the model input by the tokenizer so the
+    /// closing special tokens are kept.
+    fn encode(&self, text: &str) -> Result<(Vec<u32>, Vec<u32>)> {
+        let encoded = self
+            .tokenizer
+            .encode(text, true)
+            .map_err(|e| anyhow::anyhow!("Tokenization error: {}", e))?;
+
+        Ok((
+            encoded.get_ids().to_vec(),
+            encoded.get_attention_mask().to_vec(),
+        ))
+    }
+
+    /// Runs one forward pass over a batch padded to its longest sequence and
+    /// pools the non-padding tokens.
+    fn forward(&self, encodings: &[&(Vec<u32>, Vec<u32>)]) -> Result<Vec<Vec<f32>>> {
+        let max_len = encodings
+            .iter()
+            .map(|(ids, _)| ids.len())
+            .max()
+            .unwrap_or(0);
+
+        let mut input_ids = Vec::with_capacity(encodings.len() * max_len);
+        let mut attention_mask = Vec::with_capacity(encodings.len() * max_len);
+
+        for (ids, mask) in encodings {
+            input_ids.extend_from_slice(ids);
+            input_ids.extend(std::iter::repeat_n(self.pad_id, max_len - ids.len()));
+            attention_mask.extend_from_slice(mask);
+            attention_mask.extend(std::iter::repeat_n(0u32, max_len - mask.len()));
+        }
+
+        let shape = (encodings.len(), max_len);
+        let input_ids = Tensor::from_vec(input_ids, shape, &self.device)?;
+        let attention_mask = Tensor::from_vec(attention_mask, shape, &self.device)?;
+        let hidden = self
+            .model
+            .forward(&input_ids, &attention_mask)?
+            .to_dtype(DType::F32)?;
+
+        let pooled = match self.pooling {
+            Pooling::Cls => hidden.i((.., 0))?,
+            Pooling::Mean => {
+                let mask = attention_mask.to_dtype(DType::F32)?.unsqueeze(2)?;
+                let summed = hidden.broadcast_mul(&mask)?.sum(1)?;
+                let counts = mask.sum(1)?.clamp(1e-9, f64::MAX)?;
+                summed.broadcast_div(&counts)?
+            }
+            Pooling::LastToken => {
+                // Sequences are right-padded, so the last token sits at length - 1.
+                let rows = encodings
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (ids, _))| hidden.i((i, ids.len().saturating_sub(1))))
+                    .collect::<candle_core::Result<Vec<_>>>()?;
+                Tensor::stack(&rows, 0)?
+            }
+        };
+
+        if !self.normalize {
+            return Ok(pooled.to_vec2::<f32>()?);
+        }
+
+        let norms = pooled
+            .sqr()?
+            .sum_keepdim(1)?
+            .sqrt()?
+            .clamp(1e-12, f64::MAX)?;
+        let normalized = pooled.broadcast_div(&norms)?;
+
+        Ok(normalized.to_vec2::<f32>()?)
+    }
+
+    pub fn token_count(&self, text: &str) -> Result<usize> {
+        let encoded = self
+            .tokenizer
+            .encode(text, false)
+            .map_err(|e| anyhow::anyhow!("Tokenization error: {}", e))?;
+        Ok(encoded.get_ids().len())
+    }
+}
+
+#[async_trait]
+impl Embedder for CandleEmbedder {
+    async fn embed_documents(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        if self.document_prefix.is_empty() {
+            return self.embed(texts);
+        }
+        let prefixed: Vec<String> = texts
+            .iter()
+            .map(|text| format!("{}{}", self.document_prefix, text))
+            .collect();
+        self.embed(&prefixed)
+    }
+
+    async fn embed_query(&self, query: &str) -> Result<Vec<f32>> {
+        self.embed_one(&format!("{}{}", self.query_prefix, query))
+    }
+
+    fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    fn fingerprint(&self) -> ModelFingerprint {
+        ModelFingerprint {
+            model_id: self.model_id.clone(),
+            revision: self.revision.clone(),
+            pooling: self.pooling.to_string(),
+            normalize: self.normalize,
+            dimensions: self.dimensions,
+            document_prefix: self.document_prefix.clone(),
+            encoder_version: ENCODER_VERSION,
+        }
+    }
+
+    fn device(&self) -> String {
+        format!("{} ({:?})", device_name(&self.device), self.dtype)
+    }
+
+    fn document_tokenizer(&self) -> Option<(Tokenizer, usize)> {
+        Some((self.splitter.clone(), self.document_budget))
+    }
+}
+
+/// The pooling a model family was trained with, per its model card.
+fn default_pooling(model_id: &str) -> Pooling {
+    let model = model_id.to_lowercase();
+    if model.contains("bge-")
+        || model.contains("mxbai-embed")
+        || model.contains("snowflake-arctic-embed")
+    {
+        Pooling::Cls
+    } else if model.contains("qwen")
+        || model.contains("e5-mistral")
+        || model.contains("sfr-embedding")
+    {
+        Pooling::LastToken
+    } else {
+        // E5, GTE, Nomic, Jina and sentence-transformers models.
+        Pooling::Mean
+    }
+}
+
+fn select_device(config: &EmbeddingConfig) -> Result<Device> {
+    let spec = match (config.device.as_deref(), config.cuda_device) {
+        (Some(spec), _) => spec.to_string(),
+        (None, Some(ordinal)) => format!("cuda:{}", ordinal),
+        (None, None) if cfg!(feature = "cuda") => DEFAULT_CUDA_DEVICE.to_string(),
+        (None, None) => return Ok(Device::Cpu),
+    };
+
+    let ordinal = match spec.trim().to_lowercase().as_str() {
+        "cpu" => return Ok(Device::Cpu),
+        "cuda" => 0,
+        other => other
+            .strip_prefix("cuda:")
+            .and_then(|n| n.parse::<usize>().ok())
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Invalid embedding.device {:?} (expected \"cpu\", \"cuda\" or \"cuda:N\")",
+                    spec
+                )
+            })?,
+    };
+
+    if !cfg!(feature = "cuda") {
+        anyhow::bail!(
+            "Embedding device {:?} needs CUDA but brain was built without it. \
+             Rebuild with `--features cuda` or set embedding.device = \"cpu\".",
+            spec
+        );
+    }
+
+    Device::new_cuda(ordinal).with_context(|| {
+        format!(
+            "Cannot open embedding device {}; set embedding.device to another GPU or \"cpu\"",
+            spec
+        )
+    })
+}
+
+fn device_name(device: &Device) -> String {
+    match device.location() {
+        DeviceLocation::Cpu => "cpu".to_string(),
+        DeviceLocation::Cuda { gpu_id } => format!("cuda:{}", gpu_id),
+        DeviceLocation::Metal { gpu_id } => format!("metal:{}", gpu_id),
+    }
+}
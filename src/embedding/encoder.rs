@@ -0,0 +1,186 @@
+use crate::embedding::nomic_bert;
+use anyhow::Result;
+use candle_core::{DType, Device, Tensor};
+use candle_nn::{Module, VarBuilder};
+use candle_transformers::models::{bert, jina_bert, modernbert, xlm_roberta};
+use serde::Deserialize;
+use std::path::Path;
+
+/// The encoder architectures that produce sentence embeddings: those Candle
+/// provides, plus NomicBERT.
+pub(crate) enum Encoder {
+    Bert(bert::BertModel),
+    JinaBert(jina_bert::BertModel),
+    ModernBert(modernbert::ModernBert),
+    NomicBert(nomic_bert::NomicBertModel),
+    XlmRoberta(xlm_roberta::XLMRobertaModel),
+}
+
+#[derive(Deserialize)]
+struct ArchitectureConfig {
+    model_type: Option<String>,
+    #[serde(default)]
+    architectures: Vec<String>,
+    position_embedding_type: Option<String>,
+    // NomicBERT uses the GPT-2 names.
+    #[serde(alias = "n_embd")]
+    hidden_size: usize,
+    #[serde(alias = "n_positions")]
+    max_position_embeddings: usize,
+}
+
+pub(crate) struct LoadedEncoder {
+    pub encoder: Encoder,
+    /// The dtype the weights were loaded in, after any fallback to f32.
+    pub dtype: DType,
+    pub dimensions: usize,
+    /// Longest input the position embeddings cover.
+    pub max_positions: usize,
+}
+
+impl Encoder {
+    /// Detects the architecture from `config.json` and loads the weights for it.
+    ///
+    /// Half precision falls back to f32 on the CPU and for architectures whose
+    /// Candle implementation builds f32 attention masks.
+    pub fn load(
+        config_json: &str,
+        weights: &Path,
+        dtype: DType,
+        device: &Device,
+    ) -> Result<LoadedEncoder> {
+        let arch: ArchitectureConfig = serde_json::from_str(config_json)?;
+        let model_type = arch.model_type.as_deref().unwrap_or("bert");
+
+        let is_jina = arch.architectures.iter().any(|a| a.starts_with("JinaBert"))
+            || arch.position_embedding_type.as_deref() == Some("alibi");
+
+        let half_supported = device.is_cuda()
+            && matches!(
+                model_type,
+                "bert" | "nomic_bert" | "xlm-roberta" | "roberta"
+            )
+            && !is_jina;
+        let dtype = if dtype != DType::F32 && !half_supported {
+            tracing::warn!(
+                "{:?} inference is not supported for {} on {:?}; using f32",
+                dtype,
+                model_type,
+                device.location()
+            );
+            DType::F32
+        } else {
+            dtype
+        };
+
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[weights], dtype, device)? };
+
+        let (encoder, max_positions) = match model_type {
+            "bert" if is_jina => {
+                let config: jina_bert::Config = serde_json::from_str(config_json)?;
+                (
+                    Encoder::JinaBert(jina_bert::BertModel::new(vb, &config)?),
+                    arch.max_position_embeddings,
+                )
+            }
+            "bert" => {
+                let config: bert::Config = serde_json::from_str(config_json)?;
+                (
+                    Encoder::Bert(bert::BertModel::load(vb, &config)?),
+                    arch.max_position_embeddings,
+                )
+            }
+            "modernbert" => {
+                let config: modernbert::Config = serde_json::from_str(config_json)?;
+                // Base-model checkpoints lack the `model.` prefix of the masked-LM ones.
+                let vb = if vb.contains_tensor("model.embeddings.tok_embeddings.weight") {
+                    vb
+                } else {
+                    vb.rename_f(|name| name.strip_prefix("model.").unwrap_or(name).to_string())
+                };
+                (
+                    Encoder::ModernBert(modernbert::ModernBert::load(vb, &config)?),
+                    arch.max_position_embeddings,
+                )
+            }
+            "xlm-roberta" | "roberta" => {
+                let config: xlm_roberta::Config = serde_json::from_str(config_json)?;
+                let vb = if vb.contains_tensor("roberta.embeddings.word_embeddings.weight") {
+                    vb.pp("roberta")
+                } else {
+                    vb
+                };
+                // Positions are offset by the padding index, which takes two slots.
+                (
+                    Encoder::XlmRoberta(xlm_roberta::XLMRobertaModel::new(&config, vb)?),
+                    arch.max_position_embeddings.saturating_sub(2),
+                )
+            }
+            "nomic_bert" => {
+                let config: nomic_bert::Config = serde_json::from_str(config_json)?;
+                let vb = if vb.contains_tensor("bert.embeddings.word_embeddings.weight") {
+                    vb.pp("bert")
+                } else {
+                    vb
+                };
+                (
+                    Encoder::NomicBert(nomic_bert::NomicBertModel::load(vb, &config)?),
+                    arch.max_position_embeddings,
+                )
+            }
+            other => anyhow::bail!(
+                "Unsupported embedding architecture {:?} (supported: bert, jina bert, modernbert, \
+                 nomic bert, xlm-roberta)",
+                other
+            ),
+        };
+
+        Ok(LoadedEncoder {
+            encoder,
+            dtype,
+            dimensions: arch.hidden_size,
+            max_positions,
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoder::Bert(_) => "bert",
+            Encoder::JinaBert(_) => "jina-bert",
+            Encoder::ModernBert(_) => "modernbert",
+            Encoder::NomicBert(_) => "nomic-bert",
+            Encoder::XlmRoberta(_) => "xlm-roberta",
+        }
+    }
+
+    /// Hidden states `[batch, seq, hidden]` for right-padded `input_ids`.
+    pub fn forward(&self, input_ids: &Tensor, attention_mask: &Tensor) -> Result<Tensor> {
+        let hidden = match self {
+            Encoder::Bert(model) => {
+                let token_type_ids = input_ids.zeros_like()?;
+                model.forward(input_ids, &token_type_ids, Some(attention_mask))?
+            }
+            Encoder::JinaBert(model) => {
+                // JinaBERT takes no attention mask, so each sequence runs unpadded
+                // and its padding positions are filled with zeros.
+                let (batch, max_len) = input_ids.dims2()?;
+                let lengths: Vec<u32> = attention_mask.sum(1)?.to_vec1()?;
+                let rows = (0..batch)
+                    .map(|i| {
+                        let len = lengths[i] as usize;
+                        let ids = input_ids.narrow(0, i, 1)?.narrow(1, 0, len)?;
+                        model.forward(&ids)?.pad_with_zeros(1, 0, max_len - len)
+                    })
+                    .collect::<candle_core::Result<Vec<_>>>()?;
+                Tensor::cat(&rows, 0)?
+            }
+            Encoder::ModernBert(model) => model.forward(input_ids, attention_mask)?,
+            Encoder::NomicBert(model) => model.forward(input_ids, attention_mask)?,
+            Encoder::XlmRoberta(model) => {
+                let token_type_ids = input_ids.zeros_like()?;
+                model.forward(input_ids, attention_mask, &token_type_ids, None, None, None)?
+            }
+        };
+        Ok(hidden)
+    }
+}
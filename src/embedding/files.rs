@@ -0,0 +1,111 @@
+use crate::config::EmbeddingConfig;
+use anyhow::Result;
+use hf_hub::api::sync::ApiBuilder;
+use hf_hub::{Cache, Repo, RepoType};
+use std::path::PathBuf;
+
+const MODEL_FILES: [&str; 3] = ["config.json", "tokenizer.json", "model.safetensors"];
+
+pub(crate) struct ModelFiles {
+    pub config: PathBuf,
+    pub tokenizer: PathBuf,
+    pub weights: PathBuf,
+}
+
+impl ModelFiles {
+    /// Finds the model files in `embedding.model_path`, the Hugging Face cache when
+    /// offline, or downloads them at `embedding.revision`.
+    pub fn resolve(config: &EmbeddingConfig) -> Result<Self> {
+        let revision = config.revision.as_deref().unwrap_or("main");
+
+        if let Some(dir) = &config.model_path {
+            tracing::info!("Loading model files from {}", dir.display());
+            let missing: Vec<&str> = MODEL_FILES
+                .into_iter()
+                .filter(|file| !dir.join(file).is_file())
+                .collect();
+            if !missing.is_empty() {
+                anyhow::bail!(
+                    "embedding.model_path {} is missing {}",
+                    dir.display(),
+                    missing.join(", ")
+                );
+            }
+            return Ok(Self::from_paths(MODEL_FILES.map(|file| dir.join(file))));
+        }
+
+        let repo = Repo::with_revision(config.model.clone(), RepoType::Model, revision.to_string());
+
+        if config.offline || hub_offline() {
+            let cache = Cache::from_env();
+            tracing::info!(
+                "Offline: loading {}@{} from {}",
+                config.model,
+                revision,
+                cache.path().display()
+            );
+            let cache_repo = cache.repo(repo);
+
+            let mut paths = Vec::new();
+            let mut missing = Vec::new();
+            for file in MODEL_FILES {
+                match cache_repo.get(file) {
+                    Some(path) => paths.push(path),
+                    None => missing.push(file),
+                }
+            }
+            if !missing.is_empty() {
+                anyhow::bail!(
+                    "{}@{} is not fully cached in {} (missing {}). \
+                     Download it on a connected machine, or point embedding.model_path at a local copy.",
+                    config.model,
+                    revision,
+                    cache.path().display(),
+                    missing.join(", ")
+                );
+            }
+            return Ok(Self::from_paths(
+                paths.try_into().expect("one path per file"),
+            ));
+        }
+
+        tracing::info!(
+            "Downloading model files for {}@{}...",
+            config.model,
+            revision
+        );
+        let api_repo = ApiBuilder::from_env().build()?.repo(repo);
+
+        let mut paths = Vec::new();
+        let mut failures = Vec::new();
+        for file in MODEL_FILES {
+            match api_repo.get(file) {
+                Ok(path) => paths.push(path),
+                Err(e) => failures.push(format!("{} ({})", file, e)),
+            }
+        }
+        if !failures.is_empty() {
+            anyhow::bail!(
+                "Failed to fetch {}@{}: {}",
+                config.model,
+                revision,
+                failures.join(", ")
+            );
+        }
+        Ok(Self::from_paths(
+            paths.try_into().expect("one path per file"),
+        ))
+    }
+
+    fn from_paths([config, tokenizer, weights]: [PathBuf; 3]) -> Self {
+        Self {
+            config,
+            tokenizer,
+            weights,
+        }
+    }
+}
+
+fn hub_offline() -> bool {
+    std::env::var("HF_HUB_OFFLINE").is_ok_and(|v| matches!(v.as_str(), "1" | "true" | "TRUE"))
+}
@@ -0,0 +1,71 @@
+use crate::config::EmbeddingConfig;
+use crate::embedding::{Embedder, ModelFingerprint, normalize};
+use anyhow::Result;
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+
+/// Hashes lowercased tokens into `embedding.dimensions` signed buckets, so texts
+/// sharing words get similar vectors. Needs no model and is fully deterministic.
+pub struct HashEmbedder {
+    dimensions: usize,
+}
+
+impl HashEmbedder {
+    pub fn new(config: &EmbeddingConfig) -> Self {
+        Self {
+            dimensions: config.dimensions.max(1),
+        }
+    }
+
+    fn embed(&self, text: &str) -> Vec<f32> {
+        let mut embedding = vec![0.0; self.dimensions];
+
+        for token in text
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|t| !t.is_empty())
+        {
+            let digest = Sha256::digest(token.to_lowercase().as_bytes());
+            let bucket = u64::from_le_bytes(digest[..8].try_into().expect("8 bytes"));
+            let sign = if digest[8] & 1 == 0 { 1.0 } else { -1.0 };
+            embedding[(bucket % self.dimensions as u64) as usize] += sign;
+        }
+
+        normalize(&mut embedding);
+        embedding
+    }
+}
+
+#[async_trait]
+impl Embedder for HashEmbedder {
+    async fn embed_documents(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        Ok(texts.iter().map(|text| self.embed(text)).collect())
+    }
+
+    async fn embed_query(&self, query: &str) -> Result<Vec<f32>> {
+        Ok(self.embed(query))
+    }
+
+    fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    fn model_id(&self) -> &str {
+        "hash"
+    }
+
+    fn fingerprint(&self) -> ModelFingerprint {
+        ModelFingerprint {
+            model_id: "hash".to_string(),
+            revision: "sha256".to_string(),
+            pooling: "sum".to_string(),
+            normalize: true,
+            dimensions: self.dimensions,
+            document_prefix: String::new(),
+            encoder_version: 0,
+        }
+    }
+
+    fn device(&self) -> String {
+        "cpu".to_string()
+    }
+}
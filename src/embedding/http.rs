@@ -0,0 +1,154 @@
+use crate::config::EmbeddingConfig;
+use crate::embedding::{Embedder, ModelFingerprint, normalize, prefixes};
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+pub struct OpenAiEmbedder {
+    base_url: String,
+    model: String,
+    api_key: Option<String>,
+    client: Client,
+    dimensions: usize,
+    batch_size: usize,
+    normalize: bool,
+    query_prefix: String,
+    document_prefix: String,
+}
+
+#[derive(Debug, Serialize)]
+struct EmbeddingRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingData {
+    embedding: Vec<f32>,
+    index: usize,
+}
+
+impl OpenAiEmbedder {
+    pub async fn new(config: &EmbeddingConfig) -> Result<Self> {
+        let base_url = config
+            .base_url
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("embedding.base_url is required for provider openai"))?;
+        let (query_prefix, document_prefix) = prefixes(config);
+
+        let mut embedder = Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            model: config.model.clone(),
+            api_key: config.api_key.clone(),
+            client: Client::new(),
+            dimensions: 0,
+            batch_size: config.batch_size.max(1),
+            normalize: config.normalize,
+            query_prefix,
+            document_prefix,
+        };
+
+        // The server knows the model's size; ask it rather than trusting the config.
+        embedder.dimensions = embedder.request(&["dimension probe".to_string()]).await?[0].len();
+        tracing::info!(
+            "Embedding server {} serves {} with {} dimensions",
+            embedder.base_url,
+            embedder.model,
+            embedder.dimensions
+        );
+
+        Ok(embedder)
+    }
+
+    async fn request(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let url = format!("{}/v1/embeddings", self.base_url);
+
+        let mut request = self.client.post(&url).json(&EmbeddingRequest {
+            model: &self.model,
+            input: texts,
+        });
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+
+        let response = request.send().await?;
+
+        if !response.status().is_success() {
+            let error = response.text().await?;
+            anyhow::bail!("Embedding server error: {}", error);
+        }
+
+        let mut response: EmbeddingResponse = response.json().await?;
+        if response.data.len() != texts.len() {
+            anyhow::bail!(
+                "Embedding server returned {} embeddings for {} inputs",
+                response.data.len(),
+                texts.len()
+            );
+        }
+        response.data.sort_by_key(|d| d.index);
+
+        Ok(response
+            .data
+            .into_iter()
+            .map(|d| {
+                let mut embedding = d.embedding;
+                if self.normalize {
+                    normalize(&mut embedding);
+                }
+                embedding
+            })
+            .collect())
+    }
+}
+
+#[async_trait]
+impl Embedder for OpenAiEmbedder {
+    async fn embed_documents(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let prefixed: Vec<String> = texts
+            .iter()
+            .map(|text| format!("{}{}", self.document_prefix, text))
+            .collect();
+
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for batch in prefixed.chunks(self.batch_size) {
+            embeddings.extend(self.request(batch).await?);
+        }
+        Ok(embeddings)
+    }
+
+    async fn embed_query(&self, query: &str) -> Result<Vec<f32>> {
+        let input = [format!("{}{}", self.query_prefix, query)];
+        Ok(self.request(&input).await?.remove(0))
+    }
+
+    fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    fn model_id(&self) -> &str {
+        &self.model
+    }
+
+    fn fingerprint(&self) -> ModelFingerprint {
+        ModelFingerprint {
+            model_id: self.model.clone(),
+            revision: "server".to_string(),
+            pooling: "server".to_string(),
+            normalize: self.normalize,
+            dimensions: self.dimensions,
+            document_prefix: self.document_prefix.clone(),
+            encoder_version: 0,
+        }
+    }
+
+    fn device(&self) -> String {
+        format!("remote ({})", self.base_url)
+    }
+}
@@ -0,0 +1,71 @@
+use crate::embedding::{Embedder, ModelFingerprint, normalize};
+use anyhow::Result;
+use async_trait::async_trait;
+use tokenizers::Tokenizer;
+
+/// Keeps the leading `dimensions` components of each vector and re-normalizes,
+/// for models trained with Matryoshka representation learning.
+pub struct MatryoshkaEmbedder {
+    inner: Box<dyn Embedder>,
+    dimensions: usize,
+}
+
+impl MatryoshkaEmbedder {
+    pub fn new(inner: Box<dyn Embedder>, dimensions: usize) -> Result<Self> {
+        if dimensions == 0 || dimensions > inner.dimensions() {
+            anyhow::bail!(
+                "embedding.output_dimensions must be between 1 and {} for {}, got {}",
+                inner.dimensions(),
+                inner.model_id(),
+                dimensions
+            );
+        }
+        Ok(Self { inner, dimensions })
+    }
+
+    fn truncate(&self, mut embedding: Vec<f32>) -> Vec<f32> {
+        embedding.truncate(self.dimensions);
+        normalize(&mut embedding);
+        embedding
+    }
+}
+
+#[async_trait]
+impl Embedder for MatryoshkaEmbedder {
+    async fn embed_documents(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        Ok(self
+            .inner
+            .embed_documents(texts)
+            .await?
+            .into_iter()
+            .map(|e| self.truncate(e))
+            .collect())
+    }
+
+    async fn embed_query(&self, query: &str) -> Result<Vec<f32>> {
+        Ok(self.truncate(self.inner.embed_query(query).await?))
+    }
+
+    fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    fn model_id(&self) -> &str {
+        self.inner.model_id()
+    }
+
+    fn fingerprint(&self) -> ModelFingerprint {
+        ModelFingerprint {
+            dimensions: self.dimensions,
+            ..self.inner.fingerprint()
+        }
+    }
+
+    fn device(&self) -> String {
+        self.inner.device()
+    }
+
+    fn document_tokenizer(&self) -> Option<(Tokenizer, usize)> {
+        self.inner.document_tokenizer()
+    }
+}
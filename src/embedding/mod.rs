@@ -1,123 +1,179 @@
+pub mod cache;
+pub mod candle;
+mod encoder;
+mod files;
+pub mod hash;
+pub mod http;
+pub mod matryoshka;
+mod nomic_bert;
+
+pub use cache::{CacheStats, CachedEmbedder, EmbeddingCache};
+pub use candle::CandleEmbedder;
+pub use hash::HashEmbedder;
+pub use http::OpenAiEmbedder;
+pub use matryoshka::MatryoshkaEmbedder;
+
+use crate::config::{EmbeddingConfig, EmbeddingProvider};
 use anyhow::Result;
-use candle_core::{Device, Tensor};
-use candle_nn::VarBuilder;
-use candle_transformers::models::bert::{BertModel, Config as BertConfig};
-use hf_hub::{Repo, api::sync::Api};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 use tokenizers::Tokenizer;
 
-pub struct EmbeddingModel {
-    model: BertModel,
-    tokenizer: Tokenizer,
-    device: Device,
-    dimensions: usize,
+/// Everything that determines the vector space an embedding lives in.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelFingerprint {
+    pub model_id: String,
+    pub revision: String,
+    pub pooling: String,
+    pub normalize: bool,
+    pub dimensions: usize,
+    #[serde(default)]
+    pub document_prefix: String,
+    /// Version of the backend's input encoding, bumped when a fix changes the
+    /// vectors the same model produces. Zero for tables written before it.
+    #[serde(default)]
+    pub encoder_version: u32,
 }
 
-impl EmbeddingModel {
-    pub fn new(model_id: &str, cuda_device: usize) -> Result<Self> {
-        let device = Device::cuda_if_available(cuda_device)?;
-        tracing::info!("Using device: {:?}", device);
-
-        let api = Api::new()?;
-        let repo = Repo::model(model_id.to_string());
-        let api_repo = api.repo(repo);
-
-        tracing::info!("Downloading model files for {}...", model_id);
-
-        let config_path = api_repo.get("config.json")?;
-        let tokenizer_path = api_repo.get("tokenizer.json")?;
-        let weights_path = api_repo.get("model.safetensors")?;
+impl ModelFingerprint {
+    /// Human-readable list of the fields that differ from `other`.
+    pub fn differences(&self, other: &ModelFingerprint) -> Vec<String> {
+        let mut differences = Vec::new();
+        if self.model_id != other.model_id {
+            differences.push(format!("model {} vs {}", self.model_id, other.model_id));
+        }
+        if self.revision != other.revision {
+            differences.push(format!("revision {} vs {}", self.revision, other.revision));
+        }
+        if self.pooling != other.pooling {
+            differences.push(format!("pooling {} vs {}", self.pooling, other.pooling));
+        }
+        if self.normalize != other.normalize {
+            differences.push(format!(
+                "normalization {} vs {}",
+                self.normalize, other.normalize
+            ));
+        }
+        if self.document_prefix != other.document_prefix {
+            differences.push(format!(
+                "document prefix {:?} vs {:?}",
+                self.document_prefix, other.document_prefix
+            ));
+        }
+        if self.encoder_version != other.encoder_version {
+            differences.push(format!(
+                "encoder version {} vs {}",
+                self.encoder_version, other.encoder_version
+            ));
+        }
+        if self.dimensions != other.dimensions {
+            differences.push(format!(
+                "dimensions {} vs {}",
+                self.dimensions, other.dimensions
+            ));
+        }
+        differences
+    }
+}
 
-        let config: BertConfig = serde_json::from_str(&std::fs::read_to_string(config_path)?)?;
-        let dimensions = config.hidden_size;
+#[async_trait]
+pub trait Embedder: Send + Sync {
+    async fn embed_documents(&self, texts: &[String]) -> Result<Vec<Vec<f32>>>;
 
-        tracing::info!("Model config loaded: {} dimensions", dimensions);
+    async fn embed_query(&self, query: &str) -> Result<Vec<f32>>;
 
-        let tokenizer = Tokenizer::from_file(&tokenizer_path)
-            .map_err(|e| anyhow::anyhow!("Failed to load tokenizer: {}", e))?;
+    fn dimensions(&self) -> usize;
 
-        tracing::info!("Loading model weights...");
-        let vb = unsafe {
-            VarBuilder::from_mmaped_safetensors(&[weights_path], candle_core::DType::F32, &device)?
-        };
+    fn model_id(&self) -> &str;
 
-        let model = BertModel::load(vb, &config)?;
-        tracing::info!("Model loaded successfully");
+    fn fingerprint(&self) -> ModelFingerprint;
 
-        Ok(Self {
-            model,
-            tokenizer,
-            device,
-            dimensions,
-        })
-    }
+    /// Where inference runs, for status output.
+    fn device(&self) -> String;
 
-    pub fn dimensions(&self) -> usize {
-        self.dimensions
+    /// The tokenizer documents are embedded with and how many of its tokens fit
+    /// the model input next to the document prefix, for sizing chunks in tokens.
+    fn document_tokenizer(&self) -> Option<(Tokenizer, usize)> {
+        None
     }
+}
 
-    pub fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
-        let mut all_embeddings = Vec::with_capacity(texts.len());
+pub async fn create_embedder(config: &EmbeddingConfig) -> Result<Box<dyn Embedder>> {
+    let embedder: Box<dyn Embedder> = match config.provider {
+        EmbeddingProvider::Candle => Box::new(CandleEmbedder::new(config)?),
+        EmbeddingProvider::Openai => Box::new(OpenAiEmbedder::new(config).await?),
+        EmbeddingProvider::Hash => Box::new(HashEmbedder::new(config)),
+    };
 
-        for text in texts {
-            let embedding = self.embed_one(text)?;
-            all_embeddings.push(embedding);
+    match config.output_dimensions {
+        Some(dimensions) if dimensions != embedder.dimensions() => {
+            Ok(Box::new(MatryoshkaEmbedder::new(embedder, dimensions)?))
         }
+        _ => Ok(embedder),
+    }
+}
 
-        Ok(all_embeddings)
+/// Wraps `embedder` in the on-disk cache at `cache_dir` when `embedding.cache` is enabled.
+pub fn with_cache(
+    embedder: Box<dyn Embedder>,
+    config: &EmbeddingConfig,
+    cache_dir: &std::path::Path,
+) -> Result<Box<dyn Embedder>> {
+    if !config.cache.enabled {
+        return Ok(embedder);
     }
+    let cache = EmbeddingCache::open(cache_dir, config.cache.max_size_mb)?;
+    let (query_prefix, _) = prefixes(config);
+    Ok(Box::new(CachedEmbedder::new(
+        embedder,
+        cache,
+        &query_prefix,
+        config.max_length,
+    )?))
+}
 
-    pub fn embed_one(&self, text: &str) -> Result<Vec<f32>> {
-        let truncated_text = if text.len() > 8000 {
-            &text[..8000]
-        } else {
-            text
-        };
-
-        let encoded = self
-            .tokenizer
-            .encode(truncated_text, true)
-            .map_err(|e| anyhow::anyhow!("Tokenization error: {}", e))?;
-
-        let tokens = encoded.get_ids();
-        let attention_mask = encoded.get_attention_mask();
-
-        let max_tokens = 512;
-        let tokens: Vec<u32> = if tokens.len() > max_tokens {
-            tokens[..max_tokens].to_vec()
-        } else {
-            tokens.to_vec()
-        };
-        let attention_mask: Vec<u32> = if attention_mask.len() > max_tokens {
-            attention_mask[..max_tokens].to_vec()
-        } else {
-            attention_mask.to_vec()
-        };
-
-        let input_ids = Tensor::new(tokens.as_slice(), &self.device)?.unsqueeze(0)?;
-        let attention_mask_tensor =
-            Tensor::new(attention_mask.as_slice(), &self.device)?.unsqueeze(0)?;
-
-        let embeddings = self
-            .model
-            .forward(&input_ids, &attention_mask_tensor, None)?;
-
-        let mean_embedding = embeddings.mean(1)?;
-        let mean_embedding = mean_embedding.squeeze(0)?;
-
-        let embedding_vec = mean_embedding.to_vec1::<f32>()?;
-
-        let norm: f32 = embedding_vec.iter().map(|x| x * x).sum();
-        let norm = norm.sqrt();
-        let normalized_embedding: Vec<f32> = embedding_vec.iter().map(|x| x / norm).collect();
-
-        Ok(normalized_embedding)
+/// Query and document prefixes from config, falling back to the model family's.
+pub(crate) fn prefixes(config: &EmbeddingConfig) -> (String, String) {
+    let (query_prefix, document_prefix) = default_prefixes(&config.model);
+    (
+        config
+            .query_prefix
+            .clone()
+            .unwrap_or_else(|| query_prefix.to_string()),
+        config
+            .document_prefix
+            .clone()
+            .unwrap_or_else(|| document_prefix.to_string()),
+    )
+}
+
+pub(crate) fn normalize(vector: &mut [f32]) {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
     }
+}
 
-    pub fn token_count(&self, text: &str) -> Result<usize> {
-        let encoded = self
-            .tokenizer
-            .encode(text, false)
-            .map_err(|e| anyhow::anyhow!("Tokenization error: {}", e))?;
-        Ok(encoded.get_ids().len())
+/// Query and document prefixes asymmetric retrieval models were trained with.
+fn default_prefixes(model_id: &str) -> (&'static str, &'static str) {
+    let model = model_id.to_lowercase();
+    if model.contains("bge-") && model.contains("-en") {
+        (
+            "Represent this sentence for searching relevant passages: ",
+            "",
+        )
+    } else if model.contains("bge-") && model.contains("-zh") {
+        ("为这个句子生成表示以用于检索相关文章：", "")
+    } else if model.contains("e5-") && !model.contains("e5-mistral") {
+        ("query: ", "passage: ")
+    } else if model.contains("nomic-embed") || model.contains("modernbert-embed") {
+        ("search_query: ", "search_document: ")
+    } else if model.contains("mxbai-embed") {
+        (
+            "Represent this sentence for searching relevant passages: ",
+            "",
+        )
+    } else {
+        ("", "")
     }
 }
@@ -0,0 +1,412 @@
+//! NomicBERT (nomic-embed-text v1 and v1.5): a post-norm BERT with rotary
+//! position embeddings, fused QKV projections and a gated MLP. Candle has no
+//! implementation of it, so it is assembled here from `candle_nn` layers.
+
+use anyhow::Result;
+use candle_core::{D, DType, Device, Tensor};
+use candle_nn::{Embedding, LayerNorm, Linear, Module, VarBuilder};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    pub vocab_size: usize,
+    pub n_embd: usize,
+    pub n_head: usize,
+    pub n_layer: usize,
+    pub n_inner: Option<usize>,
+    #[serde(default = "default_layer_norm_epsilon")]
+    pub layer_norm_epsilon: f64,
+    #[serde(default = "default_type_vocab_size")]
+    pub type_vocab_size: usize,
+    #[serde(default = "default_pad_vocab_size_multiple")]
+    pub pad_vocab_size_multiple: usize,
+    #[serde(default = "default_activation_function")]
+    pub activation_function: String,
+    #[serde(default = "default_true")]
+    pub qkv_proj_bias: bool,
+    #[serde(default = "default_true")]
+    pub mlp_fc1_bias: bool,
+    #[serde(default = "default_true")]
+    pub mlp_fc2_bias: bool,
+    #[serde(default)]
+    pub rotary_emb_fraction: f64,
+    #[serde(default = "default_rotary_emb_base")]
+    pub rotary_emb_base: f64,
+    #[serde(default)]
+    pub rotary_emb_interleaved: bool,
+    pub rotary_scaling_factor: Option<f64>,
+    #[serde(default = "default_max_trained_positions")]
+    pub max_trained_positions: usize,
+    #[serde(default)]
+    pub prenorm: bool,
+    #[serde(default)]
+    pub moe_every_n_layers: usize,
+}
+
+fn default_layer_norm_epsilon() -> f64 {
+    1e-12
+}
+
+fn default_type_vocab_size() -> usize {
+    2
+}
+
+fn default_pad_vocab_size_multiple() -> usize {
+    1
+}
+
+fn default_activation_function() -> String {
+    "swiglu".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_rotary_emb_base() -> f64 {
+    10_000.0
+}
+
+fn default_max_trained_positions() -> usize {
+    2048
+}
+
+impl Config {
+    fn head_dim(&self) -> usize {
+        self.n_embd / self.n_head
+    }
+
+    fn rotary_dim(&self) -> usize {
+        // Rounded down to an even number of channels, as rotations work on pairs.
+        ((self.head_dim() as f64 * self.rotary_emb_fraction) as usize) & !1
+    }
+
+    fn check_supported(&self) -> Result<()> {
+        if self.prenorm {
+            anyhow::bail!("Pre-norm NomicBERT models are not supported");
+        }
+        if self.moe_every_n_layers > 0 {
+            anyhow::bail!("Mixture-of-experts NomicBERT models are not supported");
+        }
+        if self.rotary_dim() == 0 {
+            anyhow::bail!("NomicBERT models without rotary position embeddings are not supported");
+        }
+        if self.rotary_emb_interleaved {
+            anyhow::bail!("NomicBERT models with interleaved rotary embeddings are not supported");
+        }
+        if !matches!(self.activation_function.as_str(), "swiglu" | "geglu") {
+            anyhow::bail!(
+                "NomicBERT activation {:?} is not supported (supported: swiglu, geglu)",
+                self.activation_function
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Rotary embedding over the first `dim` channels of each head, with the
+/// dynamic NTK base scaling NomicBERT applies past its trained length.
+struct RotaryEmbedding {
+    dim: usize,
+    base: f64,
+    scaling_factor: Option<f64>,
+    max_trained_positions: usize,
+}
+
+impl RotaryEmbedding {
+    fn new(config: &Config) -> Self {
+        Self {
+            dim: config.rotary_dim(),
+            base: config.rotary_emb_base,
+            scaling_factor: config.rotary_scaling_factor,
+            max_trained_positions: config.max_trained_positions,
+        }
+    }
+
+    /// `cos` and `sin` tables of shape `[seq_len, dim / 2]`.
+    fn tables(&self, seq_len: usize, dtype: DType, device: &Device) -> Result<(Tensor, Tensor)> {
+        let base = match self.scaling_factor {
+            Some(factor) if seq_len > self.max_trained_positions => {
+                let scale =
+                    factor * seq_len as f64 / self.max_trained_positions as f64 - (factor - 1.0);
+                self.base * scale.powf(self.dim as f64 / (self.dim as f64 - 2.0))
+            }
+            _ => self.base,
+        };
+        let inv_freq: Vec<f32> = (0..self.dim)
+            .step_by(2)
+            .map(|i| (1.0 / base.powf(i as f64 / self.dim as f64)) as f32)
+            .collect();
+        let inv_freq = Tensor::from_vec(inv_freq, (1, self.dim / 2), device)?;
+        let positions = Tensor::arange(0u32, seq_len as u32, device)?
+            .to_dtype(DType::F32)?
+            .reshape((seq_len, 1))?;
+        let freqs = positions.broadcast_mul(&inv_freq)?;
+        Ok((freqs.cos()?.to_dtype(dtype)?, freqs.sin()?.to_dtype(dtype)?))
+    }
+
+    /// Rotates `xs` of shape `[batch, heads, seq, head_dim]`.
+    fn apply(&self, xs: &Tensor, cos: &Tensor, sin: &Tensor) -> Result<Tensor> {
+        let head_dim = xs.dim(D::Minus1)?;
+        if self.dim == head_dim {
+            return Ok(candle_nn::rotary_emb::rope(&xs.contiguous()?, cos, sin)?);
+        }
+        let rotated = xs.narrow(D::Minus1, 0, self.dim)?.contiguous()?;
+        let rotated = candle_nn::rotary_emb::rope(&rotated, cos, sin)?;
+        let rest = xs.narrow(D::Minus1, self.dim, head_dim - self.dim)?;
+        Ok(Tensor::cat(&[rotated, rest], D::Minus1)?)
+    }
+}
+
+struct Attention {
+    wqkv: Linear,
+    out_proj: Linear,
+    n_head: usize,
+    head_dim: usize,
+}
+
+impl Attention {
+    fn load(vb: VarBuilder, config: &Config) -> Result<Self> {
+        let bias = config.qkv_proj_bias;
+        Ok(Self {
+            wqkv: candle_nn::linear_b(config.n_embd, 3 * config.n_embd, bias, vb.pp("Wqkv"))?,
+            out_proj: candle_nn::linear_b(config.n_embd, config.n_embd, bias, vb.pp("out_proj"))?,
+            n_head: config.n_head,
+            head_dim: config.head_dim(),
+        })
+    }
+
+    fn forward(
+        &self,
+        xs: &Tensor,
+        mask: &Tensor,
+        rotary: &RotaryEmbedding,
+        cos: &Tensor,
+        sin: &Tensor,
+    ) -> Result<Tensor> {
+        let (batch, seq_len, hidden) = xs.dims3()?;
+        // [batch, seq, 3, heads, head_dim] -> three [batch, heads, seq, head_dim]
+        let qkv = self
+            .wqkv
+            .forward(xs)?
+            .reshape((batch, seq_len, 3, self.n_head, self.head_dim))?
+            .permute((2, 0, 3, 1, 4))?;
+        let query = rotary.apply(&qkv.get(0)?, cos, sin)?;
+        let key = rotary.apply(&qkv.get(1)?, cos, sin)?;
+        let value = qkv.get(2)?.contiguous()?;
+
+        let scores = (query.matmul(&key.t()?)? / (self.head_dim as f64).sqrt())?;
+        let scores = scores.broadcast_add(mask)?;
+        let probs = candle_nn::ops::softmax_last_dim(&scores)?;
+        let context = probs
+            .matmul(&value)?
+            .transpose(1, 2)?
+            .reshape((batch, seq_len, hidden))?;
+        Ok(self.out_proj.forward(&context)?)
+    }
+}
+
+/// `fc2(fc11(x) * act(fc12(x)))`.
+struct GatedMlp {
+    fc11: Linear,
+    fc12: Linear,
+    fc2: Linear,
+    activation: candle_nn::Activation,
+}
+
+impl GatedMlp {
+    fn load(vb: VarBuilder, config: &Config) -> Result<Self> {
+        // The hidden width is rounded up to a multiple of 256, as in the reference model.
+        let inner = config.n_inner.unwrap_or(4 * config.n_embd).div_ceil(256) * 256;
+        let activation = match config.activation_function.as_str() {
+            "geglu" => candle_nn::Activation::Gelu,
+            _ => candle_nn::Activation::Silu,
+        };
+        Ok(Self {
+            fc11: candle_nn::linear_b(config.n_embd, inner, config.mlp_fc1_bias, vb.pp("fc11"))?,
+            fc12: candle_nn::linear_b(config.n_embd, inner, config.mlp_fc1_bias, vb.pp("fc12"))?,
+            fc2: candle_nn::linear_b(inner, config.n_embd, config.mlp_fc2_bias, vb.pp("fc2"))?,
+            activation,
+        })
+    }
+}
+
+impl Module for GatedMlp {
+    fn forward(&self, xs: &Tensor) -> candle_core::Result<Tensor> {
+        let gate = self.fc12.forward(xs)?.apply(&self.activation)?;
+        (self.fc11.forward(xs)? * gate)?.apply(&self.fc2)
+    }
+}
+
+struct Block {
+    attn: Attention,
+    mlp: GatedMlp,
+    norm1: LayerNorm,
+    norm2: LayerNorm,
+}
+
+impl Block {
+    fn load(vb: VarBuilder, config: &Config) -> Result<Self> {
+        let eps = config.layer_norm_epsilon;
+        Ok(Self {
+            attn: Attention::load(vb.pp("attn"), config)?,
+            mlp: GatedMlp::load(vb.pp("mlp"), config)?,
+            norm1: candle_nn::layer_norm(config.n_embd, eps, vb.pp("norm1"))?,
+            norm2: candle_nn::layer_norm(config.n_embd, eps, vb.pp("norm2"))?,
+        })
+    }
+
+    fn forward(
+        &self,
+        xs: &Tensor,
+        mask: &Tensor,
+        rotary: &RotaryEmbedding,
+        cos: &Tensor,
+        sin: &Tensor,
+    ) -> Result<Tensor> {
+        let attended = self.attn.forward(xs, mask, rotary, cos, sin)?;
+        let xs = self.norm1.forward(&(attended + xs)?)?;
+        let mlp = self.mlp.forward(&xs)?;
+        Ok(self.norm2.forward(&(mlp + xs)?)?)
+    }
+}
+
+pub struct NomicBertModel {
+    word_embeddings: Embedding,
+    token_type_embeddings: Option<Embedding>,
+    emb_ln: LayerNorm,
+    layers: Vec<Block>,
+    rotary: RotaryEmbedding,
+}
+
+impl NomicBertModel {
+    pub fn load(vb: VarBuilder, config: &Config) -> Result<Self> {
+        config.check_supported()?;
+
+        let multiple = config.pad_vocab_size_multiple.max(1);
+        let vocab_size = config.vocab_size.div_ceil(multiple) * multiple;
+        let embeddings = vb.pp("embeddings");
+        let word_embeddings =
+            candle_nn::embedding(vocab_size, config.n_embd, embeddings.pp("word_embeddings"))?;
+        let token_type_embeddings = match config.type_vocab_size {
+            0 => None,
+            size => Some(candle_nn::embedding(
+                size,
+                config.n_embd,
+                embeddings.pp("token_type_embeddings"),
+            )?),
+        };
+
+        let layers = (0..config.n_layer)
+            .map(|i| Block::load(vb.pp(format!("encoder.layers.{}", i)), config))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            word_embeddings,
+            token_type_embeddings,
+            emb_ln: candle_nn::layer_norm(
+                config.n_embd,
+                config.layer_norm_epsilon,
+                vb.pp("emb_ln"),
+            )?,
+            layers,
+            rotary: RotaryEmbedding::new(config),
+        })
+    }
+
+    /// Hidden states `[batch, seq, hidden]`; `attention_mask` is 1 for tokens and 0 for padding.
+    pub fn forward(&self, input_ids: &Tensor, attention_mask: &Tensor) -> Result<Tensor> {
+        let (batch, seq_len) = input_ids.dims2()?;
+
+        let mut xs = self.word_embeddings.forward(input_ids)?;
+        if let Some(token_types) = &self.token_type_embeddings {
+            xs = (xs + token_types.forward(&input_ids.zeros_like()?)?)?;
+        }
+        let mut xs = self.emb_ln.forward(&xs)?;
+        let dtype = xs.dtype();
+
+        // Padding keys get a large negative bias so softmax ignores them.
+        let mask = ((attention_mask.to_dtype(DType::F32)? - 1.0)? * 1e4)?
+            .reshape((batch, 1, 1, seq_len))?
+            .to_dtype(dtype)?;
+        let (cos, sin) = self.rotary.tables(seq_len, dtype, xs.device())?;
+
+        for layer in &self.layers {
+            xs = layer.forward(&xs, &mask, &self.rotary, &cos, &sin)?;
+        }
+        Ok(xs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_nn::VarMap;
+
+    fn config() -> Config {
+        serde_json::from_str(
+            r#"{
+                "vocab_size": 50, "n_embd": 32, "n_head": 4, "n_layer": 2, "n_inner": 64,
+                "n_positions": 128, "activation_function": "swiglu", "qkv_proj_bias": false,
+                "mlp_fc1_bias": false, "mlp_fc2_bias": false, "rotary_emb_fraction": 1.0,
+                "rotary_emb_base": 1000, "rotary_scaling_factor": null
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn padding_does_not_change_token_states() {
+        let varmap = VarMap::new();
+        let vb = VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu);
+        let model = NomicBertModel::load(vb, &config()).unwrap();
+
+        let ids = Tensor::new(&[[3u32, 7, 11]], &Device::Cpu).unwrap();
+        let mask = Tensor::new(&[[1u32, 1, 1]], &Device::Cpu).unwrap();
+        let alone = model.forward(&ids, &mask).unwrap();
+
+        let ids = Tensor::new(&[[3u32, 7, 11, 0, 0], [5, 9, 0, 0, 0]], &Device::Cpu).unwrap();
+        let mask = Tensor::new(&[[1u32, 1, 1, 0, 0], [1, 1, 0, 0, 0]], &Device::Cpu).unwrap();
+        let padded = model.forward(&ids, &mask).unwrap();
+        assert_eq!(padded.dims(), &[2, 5, 32]);
+
+        let difference = (padded.narrow(0, 0, 1).unwrap().narrow(1, 0, 3).unwrap() - alone)
+            .unwrap()
+            .abs()
+            .unwrap()
+            .max_all()
+            .unwrap()
+            .to_scalar::<f32>()
+            .unwrap();
+        assert!(
+            difference < 1e-4,
+            "padding changed hidden states by {}",
+            difference
+        );
+    }
+
+    #[test]
+    fn rotates_only_the_rotary_fraction() {
+        let rotary = RotaryEmbedding {
+            dim: 4,
+            base: 10_000.0,
+            scaling_factor: None,
+            max_trained_positions: 2048,
+        };
+        let xs = Tensor::ones((1, 1, 3, 8), DType::F32, &Device::Cpu).unwrap();
+        let (cos, sin) = rotary.tables(3, DType::F32, &Device::Cpu).unwrap();
+        let rotated = rotary.apply(&xs, &cos, &sin).unwrap();
+
+        let values: Vec<Vec<f32>> = rotated
+            .squeeze(0)
+            .unwrap()
+            .squeeze(0)
+            .unwrap()
+            .to_vec2()
+            .unwrap();
+        // Position 0 is not rotated, and the channels past the fraction never are.
+        assert_eq!(values[0], vec![1.0; 8]);
+        assert_eq!(values[2][4..], [1.0; 4]);
+        assert_ne!(values[2][..4], [1.0; 4]);
+    }
+}
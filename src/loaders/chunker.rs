@@ -1,12 +1,39 @@
+use crate::loaders::code;
+use crate::loaders::markup::{self, Markup};
+use std::ops::Range;
+use tokenizers::Tokenizer;
+
 pub struct TextChunker {
     chunk_size: usize,
     chunk_overlap: usize,
+    /// Counts sizes in tokens of the embedding model instead of words.
+    tokenizer: Option<Tokenizer>,
 }
 
+/// A slice of a file's text, located by 1-based lines and byte offsets.
 #[derive(Debug, Clone)]
 pub struct Chunk {
     pub content: String,
     pub index: usize,
+    pub start_line: usize,
+    pub end_line: usize,
+    /// Byte range of `content` in the file.
+    pub start_byte: usize,
+    pub end_byte: usize,
+    /// Name of the code item the chunk holds, such as `Type::method`; several
+    /// names for merged small items.
+    pub symbol: Option<String>,
+    /// Heading path of the document section, such as `Guide > Connection pools`.
+    pub heading: Option<String>,
+}
+
+/// The text embedded for a chunk: its content, after the heading path of its
+/// section when it has one.
+pub fn embedding_text(content: &str, heading: Option<&str>) -> String {
+    match heading {
+        Some(heading) => format!("{}\n\n{}", heading, content),
+        None => content.to_string(),
+    }
 }
 
 impl TextChunker {
@@ -14,115 +41,468 @@ impl TextChunker {
         Self {
             chunk_size,
             chunk_overlap,
+            tokenizer: None,
         }
     }
 
-    pub fn chunk(&self, text: &str) -> Vec<Chunk> {
-        let words: Vec<&str> = text.split_whitespace().collect();
+    /// A chunker that counts `chunk_size` and `chunk_overlap` in tokens of
+    /// `tokenizer`, normally the embedding model's, so chunks fit its input.
+    pub fn with_tokenizer(
+        mut tokenizer: Tokenizer,
+        chunk_size: usize,
+        chunk_overlap: usize,
+    ) -> Self {
+        // Sizes are measured on whole texts.
+        tokenizer.with_padding(None);
+        let _ = tokenizer.with_truncation(None);
+        Self {
+            chunk_size: chunk_size.max(1),
+            chunk_overlap,
+            tokenizer: Some(tokenizer),
+        }
+    }
 
-        if words.is_empty() {
-            return Vec::new();
+    pub fn chunk(&self, text: &str) -> Vec<Chunk> {
+        if let Some(spans) = self.token_spans(text) {
+            return self.chunk_tokens(text, &spans);
         }
 
+        let words: Vec<Range<usize>> = text
+            .split_whitespace()
+            .map(|word| {
+                let offset = offset_in(text, word);
+                offset..offset + word.len()
+            })
+            .collect();
+
+        let lines = LineIndex::new(text);
         let mut chunks = Vec::new();
         let mut start = 0;
-        let mut index = 0;
 
         while start < words.len() {
             let end = (start + self.chunk_size).min(words.len());
-            let chunk_words = &words[start..end];
-            let content = chunk_words.join(" ");
-
-            if !content.trim().is_empty() {
-                chunks.push(Chunk { content, index });
-                index += 1;
-            }
+            let range = words[start].start..words[end - 1].end;
+            chunks.push(slice_chunk(text, &lines, range, chunks.len()));
 
             if end >= words.len() {
                 break;
             }
 
-            start = end.saturating_sub(self.chunk_overlap);
+            start = end.saturating_sub(self.chunk_overlap).max(start + 1);
         }
 
         chunks
     }
 
-    pub fn chunk_by_paragraphs(&self, text: &str) -> Vec<Chunk> {
-        let paragraphs: Vec<&str> = text.split("\n\n").collect();
+    /// Windows of `chunk_size` tokens overlapping by `chunk_overlap`, as slices of `text`.
+    fn chunk_tokens(&self, text: &str, spans: &[Range<usize>]) -> Vec<Chunk> {
+        let lines = LineIndex::new(text);
+        let step = self.chunk_size.saturating_sub(self.chunk_overlap).max(1);
         let mut chunks = Vec::new();
-        let mut current_chunk = String::new();
+        let mut start = 0;
+
+        while start < spans.len() {
+            let end = (start + self.chunk_size).min(spans.len());
+            let range = spans[start].start..spans[end - 1].end;
+            chunks.push(slice_chunk(text, &lines, range, chunks.len()));
+
+            if end == spans.len() {
+                break;
+            }
+            start += step;
+        }
+
+        chunks
+    }
+
+    pub fn chunk_by_paragraphs(&self, text: &str) -> Vec<Chunk> {
+        let measure = self.measure(text);
+        let mut ranges = Vec::new();
+        let mut current: Option<Range<usize>> = None;
         let mut current_size = 0;
-        let mut index = 0;
-
-        for para in paragraphs {
-            let para_words = para.split_whitespace().count();
-
-            if current_size + para_words > self.chunk_size && !current_chunk.is_empty() {
-                if !current_chunk.trim().is_empty() {
-                    chunks.push(Chunk {
-                        content: current_chunk.trim().to_string(),
-                        index,
-                    });
-                    index += 1;
-                }
-                current_chunk = String::new();
-                current_size = 0;
+
+        for para in text.split("\n\n") {
+            if para.trim().is_empty() {
+                continue;
             }
+            let offset = offset_in(text, para);
+            let para_size = measure(offset..offset + para.len());
 
-            if !current_chunk.is_empty() {
-                current_chunk.push_str("\n\n");
+            match &mut current {
+                Some(range) if current_size + para_size <= self.chunk_size => {
+                    range.end = offset + para.len();
+                }
+                _ => {
+                    ranges.extend(current.replace(offset..offset + para.len()));
+                    current_size = 0;
+                }
             }
-            current_chunk.push_str(para);
-            current_size += para_words;
+            current_size += para_size;
         }
+        ranges.extend(current);
 
-        if !current_chunk.trim().is_empty() {
-            chunks.push(Chunk {
-                content: current_chunk.trim().to_string(),
-                index,
-            });
-        }
+        let lines = LineIndex::new(text);
+        let chunks = ranges
+            .into_iter()
+            .enumerate()
+            .map(|(index, range)| slice_chunk(text, &lines, range, index))
+            .collect();
 
-        chunks
+        self.split_oversized(chunks)
     }
 
-    pub fn chunk_code(&self, code: &str) -> Vec<Chunk> {
-        let lines: Vec<&str> = code.lines().collect();
-        let mut chunks = Vec::new();
-        let mut current_lines: Vec<&str> = Vec::new();
+    /// Splits Markdown, reStructuredText and AsciiDoc at sections, keeping code
+    /// blocks, lists and tables whole where they fit, and records each chunk's
+    /// heading path. Other documents are split by paragraphs.
+    pub fn chunk_document(&self, text: &str, extension: &str) -> Vec<Chunk> {
+        let Some(markup) = Markup::from_extension(extension) else {
+            return self.chunk_by_paragraphs(text);
+        };
+
+        let measure = self.measure(text);
+        let budget = |heading: Option<&str>| self.budget(heading);
+        let pieces = markup::split(text, markup, &budget, self.chunk_overlap, &measure);
+
+        let lines = LineIndex::new(text);
+        let chunks = pieces
+            .into_iter()
+            .enumerate()
+            .map(|(index, piece)| Chunk {
+                heading: piece.heading,
+                ..slice_chunk(text, &lines, piece.range, index)
+            })
+            .collect();
+
+        self.split_oversized(chunks)
+    }
+
+    /// Splits source code into one chunk per item (function, impl, class...) for
+    /// languages with a grammar, and into windows of lines for the rest.
+    pub fn chunk_code(&self, code: &str, extension: &str) -> Vec<Chunk> {
+        let measure = self.measure(code);
+        let Some(pieces) = code::split(code, extension, self.chunk_size, &measure) else {
+            return self.split_oversized(self.chunk_lines(code, &measure));
+        };
+
+        let lines = LineIndex::new(code);
+        let chunks = pieces
+            .into_iter()
+            .enumerate()
+            .map(|(index, piece)| Chunk {
+                symbol: piece.symbol,
+                ..slice_chunk(code, &lines, piece.range, index)
+            })
+            .collect();
+
+        self.split_oversized(chunks)
+    }
+
+    fn chunk_lines(&self, code: &str, measure: &dyn Fn(Range<usize>) -> usize) -> Vec<Chunk> {
+        let lines: Vec<Range<usize>> = code
+            .lines()
+            .map(|line| {
+                let offset = offset_in(code, line);
+                offset..offset + line.len()
+            })
+            .collect();
+        let mut ranges = Vec::new();
+        let mut current_start = 0;
         let mut current_size = 0;
-        let mut index = 0;
 
-        for line in lines {
-            let line_words = line.split_whitespace().count();
+        for (i, line) in lines.iter().enumerate() {
+            let line_size = measure(line.clone());
 
-            if current_size + line_words > self.chunk_size && !current_lines.is_empty() {
-                let content = current_lines.join("\n");
-                if !content.trim().is_empty() {
-                    chunks.push(Chunk { content, index });
-                    index += 1;
-                }
+            if current_size + line_size > self.chunk_size && i > current_start {
+                ranges.push(lines[current_start].start..lines[i - 1].end);
 
-                let overlap_start = current_lines.len().saturating_sub(5);
-                current_lines = current_lines[overlap_start..].to_vec();
-                current_size = current_lines
+                // Consecutive windows share up to five lines.
+                current_start = current_start.max(i.saturating_sub(5));
+                current_size = measure(lines[current_start].start..lines[i - 1].end);
+            }
+
+            current_size += line_size;
+        }
+
+        if let Some(last) = lines.last() {
+            ranges.push(lines[current_start].start..last.end);
+        }
+
+        let index = LineIndex::new(code);
+        ranges
+            .into_iter()
+            .filter(|range| !code[range.clone()].trim().is_empty())
+            .enumerate()
+            .map(|(i, range)| slice_chunk(code, &index, range, i))
+            .collect()
+    }
+
+    /// Byte ranges of the tokens of `text`, when sizing by tokens.
+    fn token_spans(&self, text: &str) -> Option<Vec<Range<usize>>> {
+        let tokenizer = self.tokenizer.as_ref()?;
+        match tokenizer.encode(text, false) {
+            Ok(encoding) => Some(
+                encoding
+                    .get_offsets()
                     .iter()
-                    .map(|l: &&str| l.split_whitespace().count())
-                    .sum();
+                    .map(|&(start, end)| start..end)
+                    .collect(),
+            ),
+            Err(e) => {
+                tracing::warn!("Tokenization failed, sizing by words: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Sizes byte ranges of `text` in tokens, or in words without a tokenizer.
+    fn measure<'a>(&self, text: &'a str) -> impl Fn(Range<usize>) -> usize + 'a {
+        let starts: Option<Vec<usize>> = self
+            .token_spans(text)
+            .map(|spans| spans.iter().map(|span| span.start).collect());
+
+        move |range: Range<usize>| match &starts {
+            Some(starts) => {
+                starts.partition_point(|&s| s < range.end)
+                    - starts.partition_point(|&s| s < range.start)
             }
+            None => text[range].split_whitespace().count(),
+        }
+    }
+
+    /// The size left for a chunk's content once its heading path is embedded
+    /// with it.
+    fn budget(&self, heading: Option<&str>) -> usize {
+        let Some(heading) = heading else {
+            return self.chunk_size;
+        };
+        let context = embedding_text("", Some(heading));
+        let context_size = self.measure(&context)(0..context.len());
+        self.chunk_size.saturating_sub(context_size).max(1)
+    }
+
+    /// Whether `chunk` fits its token budget together with its heading path.
+    /// Always true when sizing by words.
+    pub fn fits(&self, chunk: &Chunk) -> bool {
+        match self.token_spans(&chunk.content) {
+            Some(spans) => spans.len() <= self.budget(chunk.heading.as_deref()),
+            None => true,
+        }
+    }
 
-            current_lines.push(line);
-            current_size += line_words;
+    /// Splits chunks over their token budget at token boundaries, so a long
+    /// paragraph or line cannot overflow the model input.
+    pub fn split_oversized(&self, chunks: Vec<Chunk>) -> Vec<Chunk> {
+        if self.tokenizer.is_none() {
+            return chunks;
         }
 
-        if !current_lines.is_empty() {
-            let content = current_lines.join("\n");
-            if !content.trim().is_empty() {
-                chunks.push(Chunk { content, index });
+        let mut fitted = Vec::with_capacity(chunks.len());
+        let mut split = false;
+
+        for chunk in chunks {
+            let budget = self.budget(chunk.heading.as_deref());
+            let spans = match self.token_spans(&chunk.content) {
+                Some(spans) if spans.len() > budget => spans,
+                _ => {
+                    fitted.push(chunk);
+                    continue;
+                }
+            };
+            split = true;
+
+            // Pieces start where the previous one ended, so they cover the chunk exactly.
+            let mut boundaries: Vec<usize> = spans
+                .chunks(budget)
+                .skip(1)
+                .map(|window| window[0].start)
+                .collect();
+            boundaries.insert(0, 0);
+            boundaries.push(chunk.content.len());
+
+            let lines = LineIndex::new(&chunk.content);
+            for bounds in boundaries.windows(2) {
+                if chunk.content[bounds[0]..bounds[1]].trim().is_empty() {
+                    continue;
+                }
+                let piece = slice_chunk(&chunk.content, &lines, bounds[0]..bounds[1], 0);
+                fitted.push(Chunk {
+                    start_line: chunk.start_line + piece.start_line - 1,
+                    end_line: chunk.start_line + piece.end_line - 1,
+                    start_byte: chunk.start_byte + piece.start_byte,
+                    end_byte: chunk.start_byte + piece.end_byte,
+                    symbol: chunk.symbol.clone(),
+                    heading: chunk.heading.clone(),
+                    ..piece
+                });
             }
         }
 
-        chunks
+        if split {
+            for (index, chunk) in fitted.iter_mut().enumerate() {
+                chunk.index = index;
+            }
+        }
+
+        fitted
+    }
+}
+
+/// The chunk holding `range` of `text` without surrounding blank space; the
+/// indentation of its first line is kept.
+fn slice_chunk(text: &str, lines: &LineIndex, range: Range<usize>, index: usize) -> Chunk {
+    let content = &text[range.clone()];
+    let first = range.start + (content.len() - content.trim_start().len());
+    let start = text[range.start..first]
+        .rfind('\n')
+        .map_or(range.start, |i| range.start + i + 1);
+    let end = (range.start + content.trim_end().len()).max(start);
+    Chunk {
+        content: text[start..end].to_string(),
+        index,
+        start_line: lines.line_at(start),
+        end_line: lines.line_at(end.saturating_sub(1).max(start)),
+        start_byte: start,
+        end_byte: end,
+        symbol: None,
+        heading: None,
+    }
+}
+
+/// Byte offset of `slice` within `text`, which it must borrow from.
+fn offset_in(text: &str, slice: &str) -> usize {
+    slice.as_ptr() as usize - text.as_ptr() as usize
+}
+
+/// Maps byte offsets in a text to 1-based line numbers.
+struct LineIndex {
+    line_starts: Vec<usize>,
+}
+
+impl LineIndex {
+    fn new(text: &str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self { line_starts }
+    }
+
+    fn line_at(&self, offset: usize) -> usize {
+        self.line_starts.partition_point(|&start| start <= offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use tokenizers::models::wordlevel::WordLevel;
+    use tokenizers::pre_tokenizers::whitespace::Whitespace;
+
+    /// A tokenizer with one token per word or punctuation run.
+    fn word_tokenizer() -> Tokenizer {
+        let vocab = HashMap::from([("[UNK]".to_string(), 0)]);
+        let model = WordLevel::builder()
+            .vocab(vocab.into_iter().collect())
+            .unk_token("[UNK]".to_string())
+            .build()
+            .unwrap();
+        let mut tokenizer = Tokenizer::new(model);
+        tokenizer.with_pre_tokenizer(Some(Whitespace));
+        tokenizer
+    }
+
+    fn assert_located(text: &str, chunks: &[Chunk]) {
+        for chunk in chunks {
+            assert_eq!(chunk.content, text[chunk.start_byte..chunk.end_byte]);
+            assert_eq!(
+                chunk.start_line,
+                text[..chunk.start_byte].matches('\n').count() + 1
+            );
+            assert_eq!(
+                chunk.end_line,
+                chunk.start_line + chunk.content.matches('\n').count()
+            );
+        }
+    }
+
+    #[test]
+    fn line_index_maps_offsets_to_lines() {
+        let lines = LineIndex::new("ab\ncd\n\nef");
+        let at: Vec<usize> = (0..9).map(|offset| lines.line_at(offset)).collect();
+        assert_eq!(at, [1, 1, 1, 2, 2, 2, 3, 4, 4]);
+    }
+
+    #[test]
+    fn slice_chunk_trims_blank_space_but_keeps_indentation() {
+        let text = "first\n\n    indented\n  body\n\n\nlast";
+        let lines = LineIndex::new(text);
+        let chunk = slice_chunk(text, &lines, 5..text.len() - 4, 3);
+
+        assert_eq!(chunk.content, "    indented\n  body");
+        assert_eq!((chunk.start_line, chunk.end_line), (3, 4));
+        assert_eq!(chunk.index, 3);
+        assert_located(text, &[chunk]);
+    }
+
+    #[test]
+    fn word_windows_overlap_and_stay_exact() {
+        let text = "one two three\nfour  five\n\nsix seven eight nine";
+        let chunks = TextChunker::new(4, 1).chunk(text);
+
+        let contents: Vec<&str> = chunks.iter().map(|c| c.content.as_str()).collect();
+        assert_eq!(
+            contents,
+            [
+                "one two three\nfour",
+                "four  five\n\nsix seven",
+                "seven eight nine"
+            ]
+        );
+        assert_located(text, &chunks);
+    }
+
+    #[test]
+    fn chunks_fit_together_with_long_heading_paths() {
+        let text = "# One two three four five six\n\nalpha beta gamma delta epsilon zeta\n";
+        let chunker = TextChunker::with_tokenizer(word_tokenizer(), 8, 0);
+        let chunks = chunker.chunk_document(text, "md");
+
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            let embedded = embedding_text(&chunk.content, chunk.heading.as_deref());
+            assert!(chunker.token_spans(&embedded).unwrap().len() <= 8);
+        }
+        assert_located(text, &chunks);
+    }
+
+    #[test]
+    fn unparsable_code_is_chunked_by_lines() {
+        let code = "fn broken( {\n    let x = ;\n}\n\nfn fine() {\n    ok()\n}\n";
+        let chunks = TextChunker::new(6, 0).chunk_code(code, "rs");
+
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|chunk| chunk.symbol.is_none()));
+        assert_located(code, &chunks);
+    }
+
+    #[test]
+    fn split_oversized_cuts_long_paragraphs_at_tokens() {
+        let text = "Short one.\n\nA long paragraph that goes on,\nand on for far too many words.";
+        let chunker = TextChunker::with_tokenizer(word_tokenizer(), 6, 0);
+        let chunks = chunker.chunk_by_paragraphs(text);
+
+        assert_eq!(chunks[0].content, "Short one.");
+        assert!(chunks.len() > 2);
+        for (index, chunk) in chunks.iter().enumerate() {
+            assert_eq!(chunk.index, index);
+            assert!(chunker.fits(chunk));
+        }
+        // The pieces cover the paragraph up to blank space between them.
+        assert_eq!(chunks[1].start_byte, 12);
+        assert_eq!(chunks.last().unwrap().end_byte, text.len());
+        for pair in chunks[1..].windows(2) {
+            assert!(text[pair[0].end_byte..pair[1].start_byte].trim().is_empty());
+        }
+        assert_located(text, &chunks);
     }
 }
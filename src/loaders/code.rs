@@ -0,0 +1,503 @@
+use std::ops::Range;
+use tree_sitter::{Language, Node, Parser};
+
+/// A contiguous slice of a source file and the item it belongs to.
+pub(crate) struct Piece {
+    pub range: Range<usize>,
+    pub symbol: Option<String>,
+}
+
+struct Grammar {
+    language: Language,
+    /// Node kinds that are items with a symbol name (functions, types, impls, classes...).
+    items: &'static [&'static str],
+    /// Joins a nested item's name to its parent's, e.g. `Type::method`.
+    separator: &'static str,
+}
+
+const RUST_ITEMS: &[&str] = &[
+    "function_item",
+    "function_signature_item",
+    "impl_item",
+    "struct_item",
+    "enum_item",
+    "union_item",
+    "trait_item",
+    "mod_item",
+    "macro_definition",
+    "const_item",
+    "static_item",
+    "type_item",
+];
+
+const PYTHON_ITEMS: &[&str] = &[
+    "function_definition",
+    "class_definition",
+    "decorated_definition",
+];
+
+const JAVASCRIPT_ITEMS: &[&str] = &[
+    "function_declaration",
+    "generator_function_declaration",
+    "class_declaration",
+    "abstract_class_declaration",
+    "method_definition",
+    "interface_declaration",
+    "type_alias_declaration",
+    "enum_declaration",
+    "internal_module",
+    "lexical_declaration",
+    "export_statement",
+];
+
+const GO_ITEMS: &[&str] = &[
+    "function_declaration",
+    "method_declaration",
+    "type_declaration",
+];
+
+const C_ITEMS: &[&str] = &[
+    "function_definition",
+    "struct_specifier",
+    "union_specifier",
+    "enum_specifier",
+    "class_specifier",
+    "namespace_definition",
+    "template_declaration",
+    "type_definition",
+];
+
+const JAVA_ITEMS: &[&str] = &[
+    "class_declaration",
+    "interface_declaration",
+    "enum_declaration",
+    "record_declaration",
+    "annotation_type_declaration",
+    "method_declaration",
+    "constructor_declaration",
+];
+
+fn grammar(extension: &str) -> Option<Grammar> {
+    let (language, items, separator): (Language, _, _) = match extension {
+        "rs" => (tree_sitter_rust::LANGUAGE.into(), RUST_ITEMS, "::"),
+        "py" => (tree_sitter_python::LANGUAGE.into(), PYTHON_ITEMS, "."),
+        "js" | "jsx" | "mjs" | "cjs" => (
+            tree_sitter_javascript::LANGUAGE.into(),
+            JAVASCRIPT_ITEMS,
+            ".",
+        ),
+        "ts" | "mts" | "cts" => (
+            tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+            JAVASCRIPT_ITEMS,
+            ".",
+        ),
+        "tsx" => (
+            tree_sitter_typescript::LANGUAGE_TSX.into(),
+            JAVASCRIPT_ITEMS,
+            ".",
+        ),
+        "go" => (tree_sitter_go::LANGUAGE.into(), GO_ITEMS, "."),
+        "c" => (tree_sitter_c::LANGUAGE.into(), C_ITEMS, "::"),
+        // Headers are parsed as C++, which accepts nearly all C.
+        "h" | "hpp" | "hh" | "hxx" | "cpp" | "cc" | "cxx" => {
+            (tree_sitter_cpp::LANGUAGE.into(), C_ITEMS, "::")
+        }
+        "java" => (tree_sitter_java::LANGUAGE.into(), JAVA_ITEMS, "."),
+        _ => return None,
+    };
+    Some(Grammar {
+        language,
+        items,
+        separator,
+    })
+}
+
+/// Splits `code` into one piece per top-level item, splitting items larger than
+/// `max_size` into their members (methods, statements) and merging runs of tiny
+/// siblings. `size` measures a byte range of `code`, in words or tokens. Returns
+/// `None` for languages without a grammar or code that fails to parse.
+pub(crate) fn split(
+    code: &str,
+    extension: &str,
+    max_size: usize,
+    size: &dyn Fn(Range<usize>) -> usize,
+) -> Option<Vec<Piece>> {
+    let grammar = grammar(extension)?;
+    let mut parser = Parser::new();
+    parser.set_language(&grammar.language).ok()?;
+    let tree = parser.parse(code, None)?;
+    let root = tree.root_node();
+    // Error recovery can swallow whole items into one node; windows of lines are safer.
+    if root.has_error() {
+        return None;
+    }
+
+    let splitter = Splitter {
+        code,
+        grammar: &grammar,
+        max_size: max_size.max(1),
+        size,
+    };
+    let pieces = splitter.split_children(root, 0..code.len(), None);
+    Some(
+        pieces
+            .into_iter()
+            .filter(|p| !code[p.range.clone()].trim().is_empty())
+            .collect(),
+    )
+}
+
+struct Splitter<'a> {
+    code: &'a str,
+    grammar: &'a Grammar,
+    max_size: usize,
+    size: &'a dyn Fn(Range<usize>) -> usize,
+}
+
+/// A sibling item together with the comments and attributes above it.
+struct Unit<'t> {
+    node: Node<'t>,
+    range: Range<usize>,
+}
+
+impl Splitter<'_> {
+    /// Pieces covering `range`, one per child of `container`; the text before the
+    /// first child and after the last one joins the adjacent piece.
+    fn split_children(
+        &self,
+        container: Node,
+        range: Range<usize>,
+        scope: Option<&str>,
+    ) -> Vec<Piece> {
+        let units = self.units(container);
+        if units.is_empty() {
+            return self.split_lines(range, scope);
+        }
+
+        let last = units.len() - 1;
+        let pieces = units
+            .into_iter()
+            .enumerate()
+            .map(|(i, unit)| {
+                let start = if i == 0 {
+                    range.start
+                } else {
+                    unit.range.start
+                };
+                let end = if i == last { range.end } else { unit.range.end };
+                self.split_unit(&unit, start..end, scope)
+            })
+            .collect();
+
+        self.merge_tiny(pieces)
+    }
+
+    fn split_unit(&self, unit: &Unit, range: Range<usize>, scope: Option<&str>) -> Vec<Piece> {
+        let symbol = match self.item_name(unit.node) {
+            Some(name) => Some(match scope {
+                Some(scope) => format!("{}{}{}", scope, self.grammar.separator, name),
+                None => name,
+            }),
+            None => scope.map(str::to_string),
+        };
+
+        if (self.size)(range.clone()) <= self.max_size {
+            return vec![Piece { range, symbol }];
+        }
+
+        // Items split into their members or statements; anything else, such as
+        // a long literal or a statement inside a function, by lines.
+        let body = body(unit.node);
+        if !self.grammar.items.contains(&unit.node.kind()) || body.named_child_count() == 0 {
+            return self.split_lines(range, symbol.as_deref());
+        }
+        self.split_children(body, range, symbol.as_deref())
+    }
+
+    /// Groups the named children of `container` into units, attaching comments
+    /// and attributes to the item that follows them.
+    fn units<'t>(&self, container: Node<'t>) -> Vec<Unit<'t>> {
+        let mut units = Vec::new();
+        let mut leading: Option<usize> = None;
+        let mut cursor = container.walk();
+
+        for child in container.named_children(&mut cursor) {
+            let start = self.line_start(child.start_byte());
+            if is_trivia(child.kind()) {
+                leading.get_or_insert(start);
+                continue;
+            }
+            units.push(Unit {
+                node: child,
+                range: leading.take().unwrap_or(start)..child.end_byte(),
+            });
+        }
+
+        units
+    }
+
+    fn item_name(&self, node: Node) -> Option<String> {
+        if !self.grammar.items.contains(&node.kind()) {
+            return None;
+        }
+        let node = declaration(node);
+        let text = |n: Node| self.code[n.byte_range()].to_string();
+
+        match node.kind() {
+            "impl_item" => {
+                let ty = text(node.child_by_field_name("type")?);
+                return Some(match node.child_by_field_name("trait") {
+                    Some(tr) => format!("<{} as {}>", ty, text(tr)),
+                    None => ty,
+                });
+            }
+            // Go methods are qualified by their receiver type.
+            "method_declaration" if node.child_by_field_name("receiver").is_some() => {
+                let name = text(node.child_by_field_name("name")?);
+                let receiver = node.child_by_field_name("receiver")?.named_child(0)?;
+                let ty = text(receiver.child_by_field_name("type")?);
+                return Some(format!("{}.{}", ty.trim_start_matches('*'), name));
+            }
+            "template_declaration" => {
+                let inner = node.named_child(node.named_child_count().checked_sub(1)?)?;
+                return self.item_name_of(inner);
+            }
+            _ => {}
+        }
+
+        self.item_name_of(node)
+    }
+
+    fn item_name_of(&self, node: Node) -> Option<String> {
+        let text = |n: Node| self.code[n.byte_range()].to_string();
+
+        if let Some(name) = node.child_by_field_name("name") {
+            return Some(text(name));
+        }
+
+        // C and C++ functions are named by the innermost declarator.
+        if let Some(mut declarator) = node.child_by_field_name("declarator") {
+            while let Some(inner) = declarator.child_by_field_name("declarator") {
+                declarator = inner;
+            }
+            return Some(text(declarator));
+        }
+
+        // `type X struct {...}` and `const f = () => ...`; other variables are not items.
+        let mut cursor = node.walk();
+        let named = node.named_children(&mut cursor).find(|c| match c.kind() {
+            "type_spec" => true,
+            "variable_declarator" => c.child_by_field_name("value").is_some_and(|v| {
+                matches!(v.kind(), "arrow_function" | "function_expression" | "class")
+            }),
+            _ => false,
+        });
+        named.and_then(|c| c.child_by_field_name("name")).map(text)
+    }
+
+    /// Windows of whole lines of at most `max_size`, for items with no smaller
+    /// structure to split at.
+    fn split_lines(&self, range: Range<usize>, symbol: Option<&str>) -> Vec<Piece> {
+        let mut pieces = Vec::new();
+        let mut start = range.start;
+        let mut size = 0;
+        let mut offset = range.start;
+
+        for line in self.code[range.clone()].split_inclusive('\n') {
+            let line_size = (self.size)(offset..offset + line.len());
+            if size + line_size > self.max_size && offset > start {
+                pieces.push(Piece {
+                    range: start..offset,
+                    symbol: symbol.map(str::to_string),
+                });
+                start = offset;
+                size = 0;
+            }
+            size += line_size;
+            offset += line.len();
+        }
+
+        if start < range.end {
+            pieces.push(Piece {
+                range: start..range.end,
+                symbol: symbol.map(str::to_string),
+            });
+        }
+
+        pieces
+    }
+
+    /// Merges consecutive unsplit items under a quarter of the size budget, such
+    /// as imports, constants and one-line functions, up to the budget. Pieces of
+    /// split items only take in tiny pieces of the same item, such as a trailing
+    /// `return`, so they are not glued to their neighbours.
+    fn merge_tiny(&self, units: Vec<Vec<Piece>>) -> Vec<Piece> {
+        let tiny = (self.max_size / 4).max(1);
+        let mut merged: Vec<Piece> = Vec::with_capacity(units.len());
+        let mut last_tiny = false;
+
+        for mut pieces in units {
+            if pieces.len() != 1 {
+                merged.append(&mut pieces);
+                last_tiny = false;
+                continue;
+            }
+            let piece = pieces.remove(0);
+            let size = (self.size)(piece.range.clone());
+            let is_tiny = size < tiny;
+
+            if let Some(last) = merged.last_mut()
+                && is_tiny
+                && (last_tiny || last.symbol == piece.symbol)
+                && (self.size)(last.range.clone()) + size <= self.max_size
+            {
+                last.range.end = piece.range.end;
+                last.symbol = match (last.symbol.take(), piece.symbol) {
+                    (Some(a), Some(b)) if !a.split(", ").any(|s| s == b) => {
+                        Some(format!("{}, {}", a, b))
+                    }
+                    (a, b) => a.or(b),
+                };
+                continue;
+            }
+
+            last_tiny = is_tiny;
+            merged.push(piece);
+        }
+
+        merged
+    }
+
+    /// Moves `offset` back to the start of its line when only indentation precedes it.
+    fn line_start(&self, offset: usize) -> usize {
+        let line_start = self.code[..offset].rfind('\n').map_or(0, |i| i + 1);
+        if self.code[line_start..offset].trim().is_empty() {
+            line_start
+        } else {
+            offset
+        }
+    }
+}
+
+/// The declaration inside `export`, decorator and similar wrappers.
+fn declaration(node: Node) -> Node {
+    for field in ["definition", "declaration"] {
+        if let Some(inner) = node.child_by_field_name(field) {
+            return declaration(inner);
+        }
+    }
+    node
+}
+
+/// The node whose children are an item's members or statements.
+fn body(node: Node) -> Node {
+    let node = declaration(node);
+    if let Some(body) = node.child_by_field_name("body") {
+        return body;
+    }
+    // C++ templates wrap the declaration they parameterize.
+    if node.kind() == "template_declaration"
+        && let Some(inner) = node.named_child(node.named_child_count().saturating_sub(1))
+    {
+        return body(inner);
+    }
+    node
+}
+
+fn is_trivia(kind: &str) -> bool {
+    kind.ends_with("comment") || matches!(kind, "attribute_item" | "decorator")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(code: &str) -> impl Fn(Range<usize>) -> usize + '_ {
+        move |range| code[range].split_whitespace().count()
+    }
+
+    fn pieces(code: &str, extension: &str, max_size: usize) -> Vec<(String, Option<String>)> {
+        split(code, extension, max_size, &words(code))
+            .unwrap()
+            .into_iter()
+            .map(|p| (code[p.range].trim().to_string(), p.symbol))
+            .collect()
+    }
+
+    #[test]
+    fn unknown_extension_has_no_grammar() {
+        assert!(split("plain text", "txt", 100, &words("plain text")).is_none());
+    }
+
+    #[test]
+    fn code_that_fails_to_parse_falls_back() {
+        let code = "fn broken( {\n    let x = ;\n}\n\nfn fine() {}\n";
+        assert!(split(code, "rs", 100, &words(code)).is_none());
+    }
+
+    #[test]
+    fn splits_rust_items_and_keeps_comments_with_them() {
+        let code = "\
+/// A point.
+#[derive(Debug)]
+struct Point {
+    x: i32,
+    y: i32,
+}
+
+fn distance(a: &Point, b: &Point) -> f64 {
+    let dx = (a.x - b.x) as f64;
+    let dy = (a.y - b.y) as f64;
+    (dx * dx + dy * dy).sqrt()
+}
+";
+        let pieces = pieces(code, "rs", 40);
+        assert_eq!(pieces.len(), 2);
+        assert!(
+            pieces[0]
+                .0
+                .starts_with("/// A point.\n#[derive(Debug)]\nstruct Point")
+        );
+        assert_eq!(pieces[0].1.as_deref(), Some("Point"));
+        assert!(pieces[1].0.starts_with("fn distance"));
+        assert_eq!(pieces[1].1.as_deref(), Some("distance"));
+    }
+
+    #[test]
+    fn splits_large_items_into_qualified_members() {
+        let code = "\
+impl Display for Point {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, \"({}, {})\", self.x, self.y)
+    }
+}
+
+class Shape:
+    def area(self):
+        return self.width * self.height * self.scale
+
+    def perimeter(self):
+        return 2 * (self.width + self.height) * self.scale
+";
+        let (rust, python) = code.split_at(code.find("class").unwrap());
+
+        let rust = pieces(rust, "rs", 8);
+        assert!(
+            rust.iter()
+                .any(|(_, s)| s.as_deref() == Some("<Point as Display>::fmt"))
+        );
+
+        let python = pieces(python, "py", 12);
+        let symbols: Vec<_> = python.iter().map(|(_, s)| s.as_deref()).collect();
+        assert_eq!(symbols, [Some("Shape.area"), Some("Shape.perimeter")]);
+    }
+
+    #[test]
+    fn merges_tiny_items_and_covers_the_file() {
+        let code = "use a::b;\nuse c::d;\n\nconst X: u8 = 1;\nconst Y: u8 = 2;\n";
+        let split = split(code, "rs", 40, &words(code)).unwrap();
+        assert_eq!(split.len(), 1);
+        assert_eq!(split[0].range, 0..code.len());
+        assert_eq!(split[0].symbol.as_deref(), Some("X, Y"));
+    }
+}
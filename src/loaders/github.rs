@@ -10,9 +10,13 @@ pub struct GitHubLoader {
 
 impl GitHubLoader {
     pub fn new(repos_path: std::path::PathBuf, chunk_size: usize, chunk_overlap: usize) -> Self {
+        Self::with_chunker(repos_path, TextChunker::new(chunk_size, chunk_overlap))
+    }
+
+    pub fn with_chunker(repos_path: std::path::PathBuf, chunker: TextChunker) -> Self {
         Self {
             repos_path,
-            chunker: TextChunker::new(chunk_size, chunk_overlap),
+            chunker,
         }
     }
 
@@ -51,13 +55,6 @@ impl GitHubLoader {
     pub fn load_repo(&self, repo_path: &Path) -> Result<Vec<(String, String, Vec<Chunk>)>> {
         let mut results = Vec::new();
 
-        let code_extensions = [
-            "rs", "py", "js", "ts", "jsx", "tsx", "go", "java", "c", "cpp", "h", "hpp", "rb",
-            "php", "swift", "kt", "scala", "lua", "r", "zig",
-        ];
-
-        let doc_extensions = ["md", "txt", "rst", "adoc", "org"];
-
         for entry in walkdir::WalkDir::new(repo_path)
             .follow_links(false)
             .into_iter()
@@ -69,50 +66,92 @@ impl GitHubLoader {
                 continue;
             }
 
-            let path_str = path.to_string_lossy();
-
-            if path_str.contains("/.git/")
-                || path_str.contains("/target/")
-                || path_str.contains("/node_modules/")
-                || path_str.contains("/__pycache__/")
-            {
-                continue;
+            if let Some(document) = self.load_path(repo_path, path)? {
+                results.push(document);
             }
+        }
 
-            let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        tracing::info!("Loaded {} files from repository", results.len());
+        Ok(results)
+    }
+
+    pub fn load_files(
+        &self,
+        repo_path: &Path,
+        relative_paths: &[String],
+    ) -> Result<Vec<(String, String, Vec<Chunk>)>> {
+        let mut results = Vec::new();
 
-            let is_code = code_extensions.contains(&extension);
-            let is_doc = doc_extensions.contains(&extension);
+        for relative_path in relative_paths {
+            let path = repo_path.join(relative_path);
 
-            if !is_code && !is_doc {
+            if !path.is_file() {
                 continue;
             }
 
-            let content = match std::fs::read_to_string(path) {
-                Ok(c) => c,
-                Err(_) => continue,
-            };
-
-            if content.trim().is_empty() {
-                continue;
+            if let Some(document) = self.load_path(repo_path, &path)? {
+                results.push(document);
             }
+        }
 
-            let relative_path = path.strip_prefix(repo_path)?.to_string_lossy().to_string();
+        tracing::info!("Loaded {} changed files from repository", results.len());
+        Ok(results)
+    }
 
-            let chunks = if is_code {
-                self.chunker.chunk_code(&content)
-            } else {
-                self.chunker.chunk_by_paragraphs(&content)
-            };
+    fn load_path(
+        &self,
+        repo_path: &Path,
+        path: &Path,
+    ) -> Result<Option<(String, String, Vec<Chunk>)>> {
+        let code_extensions = [
+            "rs", "py", "js", "ts", "jsx", "tsx", "go", "java", "c", "cpp", "h", "hpp", "rb",
+            "php", "swift", "kt", "scala", "lua", "r", "zig",
+        ];
 
-            if !chunks.is_empty() {
-                let source_name = repo_path.file_name().unwrap().to_string_lossy().to_string();
+        let doc_extensions = ["md", "txt", "rst", "adoc", "org"];
 
-                results.push((source_name, relative_path, chunks));
-            }
+        let path_str = path.to_string_lossy();
+
+        if path_str.contains("/.git/")
+            || path_str.contains("/target/")
+            || path_str.contains("/node_modules/")
+            || path_str.contains("/__pycache__/")
+        {
+            return Ok(None);
         }
 
-        tracing::info!("Loaded {} files from repository", results.len());
-        Ok(results)
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+
+        let is_code = code_extensions.contains(&extension);
+        let is_doc = doc_extensions.contains(&extension);
+
+        if !is_code && !is_doc {
+            return Ok(None);
+        }
+
+        let content = match std::fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(_) => return Ok(None),
+        };
+
+        if content.trim().is_empty() {
+            return Ok(None);
+        }
+
+        let relative_path = path.strip_prefix(repo_path)?.to_string_lossy().to_string();
+
+        let chunks = if is_code {
+            self.chunker.chunk_code(&content, extension)
+        } else {
+            self.chunker.chunk_document(&content, extension)
+        };
+
+        if chunks.is_empty() {
+            return Ok(None);
+        }
+
+        let source_name = repo_path.file_name().unwrap().to_string_lossy().to_string();
+
+        Ok(Some((source_name, relative_path, chunks)))
     }
 }
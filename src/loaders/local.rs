@@ -7,9 +7,11 @@ pub struct LocalLoader {
 
 impl LocalLoader {
     pub fn new(chunk_size: usize, chunk_overlap: usize) -> Self {
-        Self {
-            chunker: TextChunker::new(chunk_size, chunk_overlap),
-        }
+        Self::with_chunker(TextChunker::new(chunk_size, chunk_overlap))
+    }
+
+    pub fn with_chunker(chunker: TextChunker) -> Self {
+        Self { chunker }
     }
 
     pub fn load_directory(&self, dir: &Path) -> anyhow::Result<Vec<(String, String, Vec<Chunk>)>> {
@@ -66,9 +68,9 @@ impl LocalLoader {
             let relative_path = path.strip_prefix(dir)?.to_string_lossy().to_string();
 
             let chunks = if is_code {
-                self.chunker.chunk_code(&content)
+                self.chunker.chunk_code(&content, &extension)
             } else {
-                self.chunker.chunk_by_paragraphs(&content)
+                self.chunker.chunk_document(&content, &extension)
             };
 
             if !chunks.is_empty() {
@@ -104,9 +106,9 @@ impl LocalLoader {
         let is_code = code_extensions.contains(&extension.as_str());
 
         let chunks = if is_code {
-            self.chunker.chunk_code(&content)
+            self.chunker.chunk_code(&content, &extension)
         } else {
-            self.chunker.chunk_by_paragraphs(&content)
+            self.chunker.chunk_document(&content, &extension)
         };
 
         let file_name = file_path.file_name().unwrap().to_string_lossy().to_string();
@@ -0,0 +1,586 @@
+use std::ops::Range;
+
+/// A contiguous slice of a document and the headings of the section it is in.
+pub(crate) struct Piece {
+    pub range: Range<usize>,
+    pub heading: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Markup {
+    Markdown,
+    Rst,
+    AsciiDoc,
+}
+
+impl Markup {
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "md" | "markdown" => Some(Markup::Markdown),
+            "rst" => Some(Markup::Rst),
+            "adoc" | "asciidoc" => Some(Markup::AsciiDoc),
+            _ => None,
+        }
+    }
+}
+
+struct Line<'a> {
+    start: usize,
+    end: usize,
+    text: &'a str,
+}
+
+impl Line<'_> {
+    fn is_blank(&self) -> bool {
+        self.text.trim().is_empty()
+    }
+
+    fn indent(&self) -> usize {
+        self.text.len() - self.text.trim_start().len()
+    }
+}
+
+/// Lines that stay together unless they alone exceed the budget: a heading, a
+/// fenced or delimited block, a list, a table or a paragraph.
+struct Block {
+    lines: Range<usize>,
+    heading: Option<(usize, String)>,
+}
+
+/// Splits `text` into pieces that never cross a section boundary, packing whole
+/// blocks up to `budget(heading_path)` and splitting larger blocks by lines.
+/// Consecutive pieces of a section share trailing blocks of up to `overlap`.
+/// `size` measures a byte range of `text`, in words or tokens.
+pub(crate) fn split(
+    text: &str,
+    markup: Markup,
+    budget: &dyn Fn(Option<&str>) -> usize,
+    overlap: usize,
+    size: &dyn Fn(Range<usize>) -> usize,
+) -> Vec<Piece> {
+    let mut offset = 0;
+    let lines: Vec<Line> = text
+        .split_inclusive('\n')
+        .map(|raw| {
+            let start = offset;
+            offset += raw.len();
+            let line = raw.trim_end_matches(['\n', '\r']);
+            Line {
+                start,
+                end: start + line.len(),
+                text: line,
+            }
+        })
+        .collect();
+
+    let blocks = Scanner {
+        lines: &lines,
+        markup,
+        rst_styles: Vec::new(),
+    }
+    .blocks();
+
+    let mut pieces = Vec::new();
+    let mut headings: Vec<(usize, String)> = Vec::new();
+    // Byte ranges of the blocks of the piece being built; headings come first.
+    let mut current: Vec<Range<usize>> = Vec::new();
+    let mut has_content = false;
+
+    let flush = |current: &[Range<usize>], path: Option<String>, pieces: &mut Vec<Piece>| {
+        if let (Some(first), Some(last)) = (current.first(), current.last()) {
+            pieces.push(Piece {
+                range: first.start..last.end,
+                heading: path,
+            });
+        }
+    };
+
+    for block in blocks {
+        let range = lines[block.lines.start].start..lines[block.lines.end - 1].end;
+
+        if let Some((level, title)) = block.heading {
+            if has_content {
+                flush(&current, heading_path(&headings), &mut pieces);
+                current.clear();
+                has_content = false;
+            }
+            while headings.last().is_some_and(|(l, _)| *l >= level) {
+                headings.pop();
+            }
+            headings.push((level, title));
+            current.push(range);
+            continue;
+        }
+
+        let path = heading_path(&headings);
+        let budget = budget(path.as_deref());
+        let start = current.first().map_or(range.start, |r| r.start);
+
+        if size(range.clone()) > budget {
+            // The section's headings stay with the first window of the block.
+            let from = if has_content {
+                flush(&current, path.clone(), &mut pieces);
+                range.start
+            } else {
+                start
+            };
+            for window in split_lines(&lines, block.lines, from, budget, size) {
+                pieces.push(Piece {
+                    range: window,
+                    heading: path.clone(),
+                });
+            }
+            current.clear();
+            has_content = false;
+            continue;
+        }
+
+        if has_content && size(start..range.end) > budget {
+            flush(&current, path, &mut pieces);
+
+            let mut kept = 0;
+            let mut carried = 0;
+            for previous in current.iter().rev() {
+                carried += size(previous.clone());
+                if carried > overlap || size(previous.start..range.end) > budget {
+                    break;
+                }
+                kept += 1;
+            }
+            let dropped = current.len() - kept;
+            current.drain(..dropped);
+        }
+
+        current.push(range);
+        has_content = true;
+    }
+
+    flush(&current, heading_path(&headings), &mut pieces);
+    pieces
+}
+
+fn heading_path(headings: &[(usize, String)]) -> Option<String> {
+    if headings.is_empty() {
+        return None;
+    }
+    let titles: Vec<&str> = headings.iter().map(|(_, title)| title.as_str()).collect();
+    Some(titles.join(" > "))
+}
+
+/// Windows of whole lines of the block, the first one starting at `from`.
+fn split_lines(
+    lines: &[Line],
+    block: Range<usize>,
+    from: usize,
+    budget: usize,
+    size: &dyn Fn(Range<usize>) -> usize,
+) -> Vec<Range<usize>> {
+    let mut windows = Vec::new();
+    let mut start = from;
+    let mut end = from;
+
+    for line in &lines[block] {
+        if end > start && size(start..line.end) > budget {
+            windows.push(start..end);
+            start = line.start;
+        }
+        end = line.end;
+    }
+    windows.push(start..end);
+
+    windows
+}
+
+struct Scanner<'l, 'a> {
+    lines: &'l [Line<'a>],
+    markup: Markup,
+    /// RST adornments (character, overlined) in order of first use; the
+    /// position is the heading level.
+    rst_styles: Vec<(char, bool)>,
+}
+
+impl Scanner<'_, '_> {
+    fn blocks(mut self) -> Vec<Block> {
+        let mut blocks = Vec::new();
+        let mut i = 0;
+
+        while i < self.lines.len() {
+            if self.lines[i].is_blank() {
+                i += 1;
+                continue;
+            }
+
+            if let Some((end, level, title)) = self.heading(i) {
+                blocks.push(Block {
+                    lines: i..end,
+                    heading: Some((level, title)),
+                });
+                i = end;
+                continue;
+            }
+
+            let end = match self.fence_end(i) {
+                Some(end) => end,
+                None => {
+                    let end = self.paragraph_end(i);
+                    self.continuation_end(i, end)
+                }
+            };
+            blocks.push(Block {
+                lines: i..end,
+                heading: None,
+            });
+            i = end;
+        }
+
+        blocks
+    }
+
+    /// The line after the heading starting at `i`, its level and its title.
+    fn heading(&mut self, i: usize) -> Option<(usize, usize, String)> {
+        let line = self.lines[i].text;
+        let next = self.lines.get(i + 1).map(|l| l.text);
+
+        match self.markup {
+            Markup::Markdown => {
+                let hashes = line.len() - line.trim_start_matches('#').len();
+                if (1..=6).contains(&hashes)
+                    && line[hashes..]
+                        .chars()
+                        .next()
+                        .is_none_or(char::is_whitespace)
+                {
+                    let title = line[hashes..].trim().trim_end_matches('#').trim_end();
+                    return Some((i + 1, hashes, title.to_string()));
+                }
+                // Setext headings are underlined with `===` or `---`.
+                let underline = next?.trim();
+                if underline.is_empty()
+                    || line.starts_with(' ')
+                    || !line.chars().any(char::is_alphanumeric)
+                    || is_list_item(line, self.markup)
+                {
+                    return None;
+                }
+                let level = if underline.chars().all(|c| c == '=') {
+                    1
+                } else if underline.chars().all(|c| c == '-') {
+                    2
+                } else {
+                    return None;
+                };
+                Some((i + 2, level, line.trim().to_string()))
+            }
+            Markup::AsciiDoc => {
+                let equals = line.len() - line.trim_start_matches('=').len();
+                if (1..=6).contains(&equals) && line[equals..].starts_with(' ') {
+                    return Some((i + 1, equals, line[equals..].trim().to_string()));
+                }
+                None
+            }
+            Markup::Rst => {
+                let next = next?;
+                // Overlined: adornment, title, the same adornment.
+                if let Some(c) = adornment(line) {
+                    let under = self.lines.get(i + 2).map(|l| l.text);
+                    if next.trim().is_empty() || under.and_then(adornment) != Some(c) {
+                        return None;
+                    }
+                    let level = self.rst_level((c, true));
+                    return Some((i + 3, level, next.trim().to_string()));
+                }
+                // Underlined: title, then an adornment at least as long.
+                let c = adornment(next)?;
+                if line.starts_with(' ')
+                    || next.trim_end().chars().count() < line.trim_end().chars().count()
+                {
+                    return None;
+                }
+                let level = self.rst_level((c, false));
+                Some((i + 2, level, line.trim().to_string()))
+            }
+        }
+    }
+
+    fn rst_level(&mut self, style: (char, bool)) -> usize {
+        match self.rst_styles.iter().position(|s| *s == style) {
+            Some(index) => index + 1,
+            None => {
+                self.rst_styles.push(style);
+                self.rst_styles.len()
+            }
+        }
+    }
+
+    /// The line after the fenced or delimited block opening at `i`, through its
+    /// closing delimiter or the end of the document.
+    fn fence_end(&self, i: usize) -> Option<usize> {
+        let open = self.lines[i].text.trim();
+        let first = open.chars().next()?;
+        let run = open.len() - open.trim_start_matches(first).len();
+
+        // Backtick and tilde fences close with a run at least as long; AsciiDoc
+        // delimiters (----, ...., ====, ****, ____, ////, ++++, |===) with the same line.
+        let backticks = matches!(first, '`' | '~') && run >= 3;
+        let opens = match self.markup {
+            Markup::Markdown => backticks,
+            Markup::AsciiDoc => {
+                (first == '`' && backticks)
+                    || open == "|==="
+                    || (run == open.len() && run >= 4 && "-.=*_/+".contains(first))
+            }
+            Markup::Rst => false,
+        };
+        if !opens {
+            return None;
+        }
+
+        let closes = |line: &str| {
+            let line = line.trim();
+            if backticks {
+                line.len() >= run && line.chars().all(|c| c == first)
+            } else {
+                line == open
+            }
+        };
+        let end = self.lines[i + 1..]
+            .iter()
+            .position(|line| closes(line.text))
+            .map_or(self.lines.len(), |p| i + p + 2);
+        Some(end)
+    }
+
+    /// The line after the run of non-blank lines starting at `i`, ending early
+    /// where a heading or fence interrupts it.
+    fn paragraph_end(&mut self, i: usize) -> usize {
+        let mut end = i + 1;
+        while end < self.lines.len() && !self.lines[end].is_blank() {
+            let marker = match self.markup {
+                Markup::Markdown => Some('#'),
+                Markup::AsciiDoc => Some('='),
+                Markup::Rst => None,
+            };
+            let heading = marker.is_some_and(|m| self.lines[end].text.starts_with(m))
+                && self.heading(end).is_some();
+            if heading || self.fence_end(end).is_some() {
+                break;
+            }
+            end += 1;
+        }
+        end
+    }
+
+    /// Extends the block at `start` over blank lines to indented content that
+    /// belongs to it (nested list content, literal blocks, directive bodies) and
+    /// to further items of the same list.
+    fn continuation_end(&self, start: usize, mut end: usize) -> usize {
+        let first = &self.lines[start];
+        let list = is_list_item(first.text, self.markup);
+
+        loop {
+            let Some(next) = (end..self.lines.len()).find(|&j| !self.lines[j].is_blank()) else {
+                return end;
+            };
+            let line = &self.lines[next];
+            let continues = line.indent() > first.indent()
+                || (list
+                    && line.indent() == first.indent()
+                    && is_list_item(line.text, self.markup));
+            if !continues {
+                return end;
+            }
+
+            end = next + 1;
+            while end < self.lines.len()
+                && !self.lines[end].is_blank()
+                && (list || self.lines[end].indent() > first.indent())
+            {
+                end += 1;
+            }
+        }
+    }
+}
+
+fn is_list_item(line: &str, markup: Markup) -> bool {
+    let line = line.trim_start();
+    let bullet = |c: char| matches!(c, '-' | '*' | '+') || (c == '.' && markup == Markup::AsciiDoc);
+
+    let rest = if let Some(c) = line.chars().next().filter(|&c| bullet(c)) {
+        // AsciiDoc nests lists by repeating the marker.
+        line.trim_start_matches(c)
+    } else if let Some(rest) = line.strip_prefix("#.") {
+        rest
+    } else {
+        let rest = line.trim_start_matches(|c: char| c.is_ascii_digit());
+        if rest.len() == line.len() {
+            return false;
+        }
+        match rest.strip_prefix(['.', ')']) {
+            Some(rest) => rest,
+            None => return false,
+        }
+    };
+    rest.starts_with(' ')
+}
+
+/// The character of an RST section adornment line such as `=====`.
+fn adornment(line: &str) -> Option<char> {
+    let line = line.trim_end();
+    let c = line.chars().next()?;
+    (line.len() >= 3 && c.is_ascii_punctuation() && line.chars().all(|x| x == c)).then_some(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pieces of `text` split by words, with their trimmed text and heading path.
+    fn pieces(text: &str, markup: Markup, budget: usize) -> Vec<(&str, Option<String>)> {
+        let size = |range: Range<usize>| text[range].split_whitespace().count();
+        split(text, markup, &|_| budget, 0, &size)
+            .into_iter()
+            .map(|p| (text[p.range].trim(), p.heading))
+            .collect()
+    }
+
+    #[test]
+    fn markdown_sections_carry_their_heading_path() {
+        let text = "\
+# Guide
+
+Intro text.
+
+## Connection pools
+
+Pools reuse connections.
+
+Setext title
+============
+
+Body.
+";
+        let pieces = pieces(text, Markup::Markdown, 100);
+        assert_eq!(pieces.len(), 3);
+        assert_eq!(pieces[0], ("# Guide\n\nIntro text.", Some("Guide".into())));
+        assert_eq!(pieces[1].1.as_deref(), Some("Guide > Connection pools"));
+        assert!(pieces[1].0.starts_with("## Connection pools"));
+        assert_eq!(pieces[2].1.as_deref(), Some("Setext title"));
+    }
+
+    #[test]
+    fn fences_and_lists_stay_whole() {
+        let text = "\
+Before.
+
+```rust
+fn main() {
+
+    # not a heading
+}
+```
+
+- one
+- two
+
+  nested paragraph
+- three
+
+After.
+";
+        let lines: Vec<Line> = text
+            .split_inclusive('\n')
+            .scan(0, |offset, raw| {
+                let start = *offset;
+                *offset += raw.len();
+                let line = raw.trim_end_matches('\n');
+                Some(Line {
+                    start,
+                    end: start + line.len(),
+                    text: line,
+                })
+            })
+            .collect();
+        let blocks: Vec<Range<usize>> = Scanner {
+            lines: &lines,
+            markup: Markup::Markdown,
+            rst_styles: Vec::new(),
+        }
+        .blocks()
+        .into_iter()
+        .map(|b| b.lines)
+        .collect();
+
+        assert_eq!(blocks, [0..1, 2..8, 9..14, 15..16]);
+    }
+
+    #[test]
+    fn oversized_blocks_split_by_lines_under_the_heading() {
+        let text = "# Title\n\none two three\nfour five six\nseven eight nine\n";
+        let pieces = pieces(text, Markup::Markdown, 7);
+        assert_eq!(
+            pieces,
+            [
+                ("# Title\n\none two three", Some("Title".into())),
+                ("four five six\nseven eight nine", Some("Title".into())),
+            ]
+        );
+    }
+
+    #[test]
+    fn rst_levels_follow_adornment_order() {
+        let text = "\
+=====
+Top
+=====
+
+Section
+-------
+
+Text.
+
+Other
+-----
+
+Sub
+~~~
+
+More.
+";
+        let headings: Vec<Option<String>> = pieces(text, Markup::Rst, 100)
+            .into_iter()
+            .map(|(_, heading)| heading)
+            .collect();
+        assert_eq!(
+            headings,
+            [
+                Some("Top > Section".into()),
+                Some("Top > Other > Sub".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn asciidoc_headings_and_delimited_blocks() {
+        let text = "= Book\n\n== Chapter\n\n----\n== not a heading\n----\n";
+        let pieces = pieces(text, Markup::AsciiDoc, 100);
+        assert_eq!(pieces.len(), 1);
+        assert_eq!(pieces[0].1.as_deref(), Some("Book > Chapter"));
+        assert!(pieces[0].0.ends_with("== not a heading\n----"));
+    }
+
+    #[test]
+    fn detects_list_items_and_adornments() {
+        assert!(is_list_item("- item", Markup::Markdown));
+        assert!(is_list_item("  12. item", Markup::Markdown));
+        assert!(is_list_item("3) item", Markup::Rst));
+        assert!(is_list_item("#. item", Markup::Rst));
+        assert!(is_list_item(".. item", Markup::AsciiDoc));
+        assert!(!is_list_item(". item", Markup::Markdown));
+        assert!(!is_list_item("-not a list", Markup::Markdown));
+        assert!(!is_list_item("2024 was a year", Markup::Markdown));
+
+        assert_eq!(adornment("~~~~~  "), Some('~'));
+        assert_eq!(adornment("=="), None);
+        assert_eq!(adornment("=-="), None);
+    }
+}
@@ -1,6 +1,8 @@
 pub mod chunker;
+mod code;
 pub mod github;
 pub mod local;
+mod markup;
 
 pub use chunker::TextChunker;
 pub use github::GitHubLoader;
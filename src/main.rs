@@ -1,14 +1,21 @@
 use anyhow::Result;
-use brain::cli::{Cli, Commands, IndexCommands, UpdateCommands};
-use brain::config::Config;
-use brain::embedding::EmbeddingModel;
+use brain::cli::{
+    BenchCommands, CacheCommands, Cli, Commands, IndexCommands, StorageCommands, UpdateCommands,
+};
+use brain::config::{Config, EmbeddingConfig, OverflowMode};
+use brain::embedding::{Embedder, EmbeddingCache, create_embedder, with_cache};
+use brain::loaders::chunker::embedding_text;
 use brain::loaders::{GitHubLoader, LocalLoader};
-use brain::rag::{MistralRsClient, RagPipeline};
-use brain::scheduler::{Scheduler, UpdateCheckResult, UpdateReport};
+use brain::rag::{IndexStats, MistralRsClient, Prune, RagPipeline, chunker_for};
+use brain::scheduler::{
+    RepoChanges, Scheduler, SourceMetadata, UpdateCheckResult, UpdateReport, UpdateRunResult,
+};
 use brain::server::{self, AppState};
-use brain::storage::{DocumentWithEmbedding, SourceType, VectorStore};
+use brain::storage::{SearchOptions, SourceType, VectorStore, parse_age};
 use clap::Parser;
+use std::collections::HashSet;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -22,13 +29,55 @@ async fn main() -> Result<()> {
     match cli.command {
         Commands::Index { source } => handle_index(&config, source).await?,
         Commands::Update { action } => handle_update(&config, action).await?,
-        Commands::Query { query, limit, json } => {
-            handle_query(&config, &query, limit, json).await?
+        Commands::Query {
+            query,
+            limit,
+            json,
+            mode,
+            min_score,
+            nprobes,
+            refine_factor,
+            filter,
+        } => {
+            let options = SearchOptions::new(limit, mode)
+                .with_filter(filter.into())
+                .with_min_score(min_score)
+                .with_ann_params(nprobes, refine_factor);
+            handle_query(&config, &query, &options, json).await?
+        }
+        Commands::Search {
+            query,
+            limit,
+            mode,
+            min_score,
+            nprobes,
+            refine_factor,
+            filter,
+        } => {
+            let options = SearchOptions::new(limit, mode)
+                .with_filter(filter.into())
+                .with_min_score(min_score)
+                .with_ann_params(nprobes, refine_factor);
+            handle_search(&config, &query, &options).await?
         }
-        Commands::Search { query, limit } => handle_search(&config, &query, limit).await?,
         Commands::Serve { host, port } => handle_serve(&config, &host, port).await?,
         Commands::Sources { json } => handle_sources(&config, json).await?,
         Commands::Delete { source } => handle_delete(&config, &source).await?,
+        Commands::Storage { action } => handle_storage(&config, action).await?,
+        Commands::MigrateEmbeddings {
+            model,
+            adopt_legacy,
+            table,
+            batch_size,
+        } => match (adopt_legacy, model) {
+            (Some(model), _) => handle_adopt_legacy(&config, &model).await?,
+            (None, Some(model)) => {
+                handle_migrate_embeddings(&config, &model, table, batch_size).await?
+            }
+            (None, None) => anyhow::bail!("--model or --adopt-legacy is required"),
+        },
+        Commands::Bench { action } => handle_bench(&config, action).await?,
+        Commands::Cache { action } => handle_cache(&config, action)?,
         Commands::Status => handle_status(&config).await?,
     }
 
@@ -37,11 +86,14 @@ async fn main() -> Result<()> {
 
 async fn init_pipeline(config: &Config) -> Result<RagPipeline> {
     tracing::info!("Loading embedding model: {}", config.embedding.model);
-    let embedding_model =
-        EmbeddingModel::new(&config.embedding.model, config.embedding.cuda_device)?;
+    let embedder = with_cache(
+        create_embedder(&config.embedding).await?,
+        &config.embedding,
+        &config.data_dir().join(&config.embedding.cache.path),
+    )?;
 
-    let dimensions = embedding_model.dimensions();
-    tracing::info!("Embedding dimensions: {}", dimensions);
+    let fingerprint = embedder.fingerprint();
+    tracing::info!("Embedding dimensions: {}", fingerprint.dimensions);
 
     let db_path = config.data_dir().join(&config.storage.lancedb_path);
     std::fs::create_dir_all(&db_path)?;
@@ -49,7 +101,8 @@ async fn init_pipeline(config: &Config) -> Result<RagPipeline> {
     let vector_store = VectorStore::new(
         &db_path.join(&config.storage.table_name),
         &config.storage.table_name,
-        dimensions,
+        fingerprint,
+        config.storage.index.clone(),
     )
     .await?;
 
@@ -60,7 +113,8 @@ async fn init_pipeline(config: &Config) -> Result<RagPipeline> {
         config.llm.temperature,
     );
 
-    Ok(RagPipeline::new(embedding_model, vector_store, llm_client))
+    Ok(RagPipeline::new(embedder, vector_store, llm_client)
+        .with_split_overflow(config.embedding.overflow == OverflowMode::Split))
 }
 
 async fn handle_index(config: &Config, source: IndexCommands) -> Result<()> {
@@ -91,43 +145,59 @@ async fn handle_index(config: &Config, source: IndexCommands) -> Result<()> {
             let repos_path = config.data_dir().join(&config.sources.repos_path);
             std::fs::create_dir_all(&repos_path)?;
 
-            let loader = GitHubLoader::new(
+            let loader = GitHubLoader::with_chunker(
                 repos_path.clone(),
-                config.brain.chunk_size,
-                config.brain.chunk_overlap,
+                pipeline.chunker(config.brain.chunk_size, config.brain.chunk_overlap),
             );
 
             let repo_path = loader.clone_or_update(&owner, &repo, &branch)?;
             let documents = loader.load_repo(&repo_path)?;
 
             let source_name = format!("github:{}/{}", owner, repo);
-            let count =
-                index_documents_simple(&pipeline, &source_name, SourceType::GitHub, documents)
-                    .await?;
+            let stats = pipeline
+                .index_documents(&source_name, SourceType::GitHub, documents, Prune::Missing)
+                .await?;
 
             let metadata = scheduler.create_github_metadata(&owner, &repo, &branch, &repo_path)?;
             metadata_store.upsert(metadata);
             scheduler.save_metadata(&metadata_store)?;
 
-            println!("Indexed {} chunks from {}/{}", count, owner, repo);
+            println!(
+                "Indexed {} chunks from {}/{} ({} embedded, {} reused)",
+                stats.total_chunks(),
+                owner,
+                repo,
+                stats.chunks_embedded,
+                stats.chunks_reused + stats.chunks_unchanged
+            );
+            print_overflow(&stats);
         }
         IndexCommands::Local { path } => {
             tracing::info!("Indexing local directory: {:?}", path);
 
-            let loader = LocalLoader::new(config.brain.chunk_size, config.brain.chunk_overlap);
+            let loader = LocalLoader::with_chunker(
+                pipeline.chunker(config.brain.chunk_size, config.brain.chunk_overlap),
+            );
 
             let documents = loader.load_directory(&path)?;
 
             let source_name = format!("local:{}", path.display());
-            let count =
-                index_documents_simple(&pipeline, &source_name, SourceType::Local, documents)
-                    .await?;
+            let stats = pipeline
+                .index_documents(&source_name, SourceType::Local, documents, Prune::Missing)
+                .await?;
 
             let metadata = scheduler.create_local_metadata(&path)?;
             metadata_store.upsert(metadata);
             scheduler.save_metadata(&metadata_store)?;
 
-            println!("Indexed {} chunks from {}", count, path.display());
+            println!(
+                "Indexed {} chunks from {} ({} embedded, {} reused)",
+                stats.total_chunks(),
+                path.display(),
+                stats.chunks_embedded,
+                stats.chunks_reused + stats.chunks_unchanged
+            );
+            print_overflow(&stats);
         }
         IndexCommands::Defaults => {
             tracing::info!("Indexing default repositories...");
@@ -135,10 +205,9 @@ async fn handle_index(config: &Config, source: IndexCommands) -> Result<()> {
             let repos_path = config.data_dir().join(&config.sources.repos_path);
             std::fs::create_dir_all(&repos_path)?;
 
-            let loader = GitHubLoader::new(
+            let loader = GitHubLoader::with_chunker(
                 repos_path.clone(),
-                config.brain.chunk_size,
-                config.brain.chunk_overlap,
+                pipeline.chunker(config.brain.chunk_size, config.brain.chunk_overlap),
             );
 
             for default in &config.sources.defaults {
@@ -149,9 +218,9 @@ async fn handle_index(config: &Config, source: IndexCommands) -> Result<()> {
                 let documents = loader.load_repo(&repo_path)?;
 
                 let source_name = format!("github:{}/{}", default.owner, default.repo);
-                let count =
-                    index_documents_simple(&pipeline, &source_name, SourceType::GitHub, documents)
-                        .await?;
+                let stats = pipeline
+                    .index_documents(&source_name, SourceType::GitHub, documents, Prune::Missing)
+                    .await?;
 
                 let metadata = scheduler.create_github_metadata(
                     &default.owner,
@@ -162,64 +231,49 @@ async fn handle_index(config: &Config, source: IndexCommands) -> Result<()> {
                 metadata_store.upsert(metadata);
 
                 println!(
-                    "Indexed {} chunks from {}/{}",
-                    count, default.owner, default.repo
+                    "Indexed {} chunks from {}/{} ({} embedded, {} reused)",
+                    stats.total_chunks(),
+                    default.owner,
+                    default.repo,
+                    stats.chunks_embedded,
+                    stats.chunks_reused + stats.chunks_unchanged
                 );
+                print_overflow(&stats);
             }
 
             scheduler.save_metadata(&metadata_store)?;
         }
-    }
-
-    Ok(())
-}
-
-async fn index_documents_simple(
-    pipeline: &brain::rag::RagPipeline,
-    source: &str,
-    source_type: SourceType,
-    documents: Vec<(String, String, Vec<brain::loaders::chunker::Chunk>)>,
-) -> Result<usize> {
-    let mut total_indexed = 0;
-    let mut batch = Vec::new();
-
-    for (_source_name, file_path, chunks) in documents {
-        for chunk in chunks {
-            let id = uuid::Uuid::new_v4().to_string();
-            let embedding = pipeline.embed(&chunk.content)?;
-
-            batch.push(DocumentWithEmbedding {
-                id,
-                content: chunk.content.clone(),
-                source: source.to_string(),
-                source_type: source_type.to_string(),
-                file_path: file_path.clone(),
-                chunk_index: chunk.index as i64,
-                created_at: chrono::Utc::now().to_rfc3339(),
-                embedding,
-            });
-
-            total_indexed += 1;
+        IndexCommands::Optimize { rebuild } => {
+            let report = if rebuild {
+                pipeline.vector_store().rebuild_vector_index().await?
+            } else {
+                pipeline.vector_store().update_indices().await?
+            };
 
-            if batch.len() >= 100 {
-                pipeline.insert_batch(batch.clone()).await?;
-                batch.clear();
-                tracing::info!("Indexed {} chunks...", total_indexed);
+            match &report.vector_index {
+                Some(index) => println!(
+                    "Vector index {}: {} of {} rows indexed ({} unindexed)",
+                    index, report.indexed_rows, report.total_rows, report.unindexed_rows
+                ),
+                None => println!(
+                    "No vector index ({} rows, minimum {})",
+                    report.total_rows, config.storage.index.min_rows
+                ),
             }
         }
     }
 
-    if !batch.is_empty() {
-        pipeline.insert_batch(batch).await?;
-    }
-
-    tracing::info!("Total indexed: {} chunks", total_indexed);
-    Ok(total_indexed)
+    Ok(())
 }
 
-async fn handle_query(config: &Config, query: &str, limit: usize, json: bool) -> Result<()> {
+async fn handle_query(
+    config: &Config,
+    query: &str,
+    options: &SearchOptions,
+    json: bool,
+) -> Result<()> {
     let pipeline = init_pipeline(config).await?;
-    let response = pipeline.query_with_sources(query, limit).await?;
+    let response = pipeline.query_with_sources(query, options).await?;
 
     if json {
         println!("{}", serde_json::to_string_pretty(&response)?);
@@ -228,7 +282,14 @@ async fn handle_query(config: &Config, query: &str, limit: usize, json: bool) ->
         if !response.sources.is_empty() {
             println!("Sources:");
             for source in response.sources {
-                println!("  - {} ({})", source.file_path, source.source);
+                let location = match (source.start_line, source.end_line) {
+                    (Some(start), Some(end)) => format!("{}:{}-{}", source.file_path, start, end),
+                    _ => source.file_path.clone(),
+                };
+                println!(
+                    "  - {} ({}, similarity {:.3})",
+                    location, source.source, source.similarity
+                );
             }
         }
     }
@@ -236,15 +297,45 @@ async fn handle_query(config: &Config, query: &str, limit: usize, json: bool) ->
     Ok(())
 }
 
-async fn handle_search(config: &Config, query: &str, limit: usize) -> Result<()> {
+async fn handle_search(config: &Config, query: &str, options: &SearchOptions) -> Result<()> {
     let pipeline = init_pipeline(config).await?;
-    let results = pipeline.search(query, limit).await?;
+    let results = pipeline.search(query, options).await?;
 
     println!("Found {} results:\n", results.len());
     for (i, result) in results.iter().enumerate() {
         println!("--- Result {} ---", i + 1);
-        println!("Source: {}", result.source);
-        println!("File: {}", result.file_path);
+        println!("Source: {} ({})", result.source, result.source_type);
+        match (result.start_byte, result.end_byte) {
+            (Some(start), Some(end)) => println!(
+                "File: {} (chunk {}, bytes {}-{})",
+                result.location(),
+                result.chunk_index,
+                start,
+                end
+            ),
+            _ => println!("File: {} (chunk {})", result.location(), result.chunk_index),
+        }
+        if let Some(symbol) = &result.symbol {
+            println!("Symbol: {}", symbol);
+        }
+        if let Some(heading) = &result.heading {
+            println!("Section: {}", heading);
+        }
+        let mut raw = Vec::new();
+        if let Some(distance) = result.distance {
+            raw.push(format!("distance {:.4}", distance));
+        }
+        if let Some(bm25) = result.bm25 {
+            raw.push(format!("bm25 {:.2}", bm25));
+        }
+        if let Some(rrf) = result.rrf {
+            raw.push(format!("rrf {:.4}", rrf));
+        }
+        if raw.is_empty() {
+            println!("Similarity: {:.3}", result.similarity);
+        } else {
+            println!("Similarity: {:.3} ({})", result.similarity, raw.join(", "));
+        }
         println!(
             "Content:\n{}\n",
             result.content.chars().take(500).collect::<String>()
@@ -386,6 +477,22 @@ async fn handle_update_check(config: &Config, scheduler: &Scheduler, json: bool)
     Ok(())
 }
 
+fn print_overflow(stats: &IndexStats) {
+    if stats.chunks_truncated > 0 {
+        println!(
+            "  {} chunks exceed the embedding model's input and were truncated \
+             (set embedding.overflow = \"split\" to embed them whole)",
+            stats.chunks_truncated
+        );
+    }
+    if stats.chunks_split > 0 {
+        println!(
+            "  {} long chunks were split to fit the embedding model",
+            stats.chunks_split
+        );
+    }
+}
+
 async fn handle_update_run(
     config: &Config,
     scheduler: &Scheduler,
@@ -431,24 +538,18 @@ async fn handle_update_run(
             {
                 let repo_path = std::path::Path::new(local_path);
                 if repo_path.exists() {
-                    let loader = GitHubLoader::new(
+                    let loader = GitHubLoader::with_chunker(
                         config.data_dir().join(&config.sources.repos_path),
-                        config.brain.chunk_size,
-                        config.brain.chunk_overlap,
+                        pipeline.chunker(config.brain.chunk_size, config.brain.chunk_overlap),
                     );
 
                     if loader.clone_or_update(owner, repo, branch).is_ok() {
                         match scheduler.has_updates(&meta, repo_path) {
                             Ok(true) => {
                                 tracing::info!("Re-indexing updated source: {}", source);
-                                pipeline.vector_store().delete_by_source(source).await?;
-
-                                let documents = loader.load_repo(repo_path)?;
-                                let count = index_documents_simple(
-                                    &pipeline,
-                                    source,
-                                    SourceType::GitHub,
-                                    documents,
+
+                                let result = update_github_source(
+                                    &pipeline, &loader, source, &meta, repo_path,
                                 )
                                 .await?;
 
@@ -456,7 +557,7 @@ async fn handle_update_run(
                                 scheduler.update_after_refresh(&mut meta, repo_path)?;
                                 metadata_store.upsert(meta);
 
-                                updated.push((source.clone(), count));
+                                updated.push(result);
                             }
                             Ok(false) => {
                                 let mut meta = meta.clone();
@@ -500,8 +601,41 @@ async fn handle_update_run(
 
         if !updated.is_empty() {
             println!("\nUpdated:");
-            for (source, count) in &updated {
-                println!("  {} ({} chunks)", source, count);
+            for result in &updated {
+                println!(
+                    "  {} ({} chunks embedded, {} reused)",
+                    result.source, result.chunks_embedded, result.chunks_reused
+                );
+
+                if let Some(snapshot) = &result.snapshot {
+                    println!("    Rollback with: brain storage rollback {}", snapshot);
+                }
+
+                if result.full_reindex {
+                    println!("    Full re-index (no usable previous commit)");
+                    continue;
+                }
+
+                let changes = &result.changes;
+                println!(
+                    "    {} added, {} modified, {} deleted, {} renamed",
+                    changes.added.len(),
+                    changes.modified.len(),
+                    changes.deleted.len(),
+                    changes.renamed.len()
+                );
+                for path in &changes.added {
+                    println!("    + {}", path);
+                }
+                for path in &changes.modified {
+                    println!("    ~ {}", path);
+                }
+                for path in &changes.deleted {
+                    println!("    - {}", path);
+                }
+                for (from, to) in &changes.renamed {
+                    println!("    > {} -> {}", from, to);
+                }
             }
         }
 
@@ -516,6 +650,84 @@ async fn handle_update_run(
     Ok(())
 }
 
+async fn update_github_source(
+    pipeline: &RagPipeline,
+    loader: &GitHubLoader,
+    source: &str,
+    meta: &SourceMetadata,
+    repo_path: &std::path::Path,
+) -> Result<UpdateRunResult> {
+    let to_commit = Scheduler::get_current_commit_hash(repo_path)?;
+
+    let changes = match &meta.last_commit_hash {
+        Some(from_commit) => match Scheduler::changes_since(repo_path, from_commit) {
+            Ok(changes) => Some(changes),
+            Err(e) => {
+                tracing::warn!(
+                    "Cannot diff {}..{} for {}: {}; falling back to full re-index",
+                    from_commit,
+                    to_commit,
+                    source,
+                    e
+                );
+                None
+            }
+        },
+        None => None,
+    };
+
+    let Some(changes) = changes else {
+        let documents = loader.load_repo(repo_path)?;
+        let stats = pipeline
+            .index_documents(source, SourceType::GitHub, documents, Prune::Missing)
+            .await?;
+
+        return Ok(UpdateRunResult {
+            source: source.to_string(),
+            from_commit: meta.last_commit_hash.clone(),
+            to_commit,
+            full_reindex: true,
+            snapshot: stats.snapshot,
+            changes: RepoChanges::default(),
+            chunks_embedded: stats.chunks_embedded,
+            chunks_reused: stats.chunks_reused + stats.chunks_unchanged,
+        });
+    };
+
+    let to_index = changes.paths_to_index();
+    let documents = loader.load_files(repo_path, &to_index)?;
+
+    // Changed files that no longer produce chunks must lose their old rows too.
+    let loaded: HashSet<&str> = documents.iter().map(|(_, path, _)| path.as_str()).collect();
+    let mut to_remove = changes.paths_to_remove();
+    to_remove.extend(
+        to_index
+            .iter()
+            .filter(|path| !loaded.contains(path.as_str()))
+            .cloned(),
+    );
+
+    let stats = pipeline
+        .index_documents(
+            source,
+            SourceType::GitHub,
+            documents,
+            Prune::Files(to_remove),
+        )
+        .await?;
+
+    Ok(UpdateRunResult {
+        source: source.to_string(),
+        from_commit: meta.last_commit_hash.clone(),
+        to_commit,
+        full_reindex: false,
+        snapshot: stats.snapshot,
+        changes,
+        chunks_embedded: stats.chunks_embedded,
+        chunks_reused: stats.chunks_reused + stats.chunks_unchanged,
+    })
+}
+
 async fn handle_update_status(config: &Config, scheduler: &Scheduler) -> Result<()> {
     let metadata_store = scheduler.load_metadata()?;
     let in_window = scheduler.is_in_download_window();
@@ -580,6 +792,7 @@ async fn handle_serve(config: &Config, host: &str, port: u16) -> Result<()> {
     println!("  POST /sources/github - Add GitHub source");
     println!("  POST /sources/local  - Add local source");
     println!("  GET  /status         - Get system status");
+    println!("  GET  /admin/stats    - Storage statistics");
 
     server::run_server(state, host, port).await
 }
@@ -607,6 +820,423 @@ async fn handle_delete(config: &Config, source: &str) -> Result<()> {
     Ok(())
 }
 
+async fn handle_storage(config: &Config, action: StorageCommands) -> Result<()> {
+    let pipeline = init_pipeline(config).await?;
+    let store = pipeline.vector_store();
+
+    match action {
+        StorageCommands::Compact => {
+            let report = store.compact().await?;
+            println!(
+                "Compacted: {} fragments removed, {} added ({} files removed, {} added)",
+                report.fragments_removed,
+                report.fragments_added,
+                report.files_removed,
+                report.files_added
+            );
+        }
+        StorageCommands::Cleanup { older_than } => {
+            let report = store.cleanup(parse_age(&older_than)?).await?;
+            println!(
+                "Removed {} old versions ({:.1} MB)",
+                report.versions_removed,
+                report.bytes_removed as f64 / 1_048_576.0
+            );
+        }
+        StorageCommands::Stats { json } => {
+            let stats = store.stats().await?;
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&stats)?);
+            } else {
+                println!("Storage Stats: {}", stats.table_name);
+                println!("  Rows: {}", stats.num_rows);
+                println!(
+                    "  Fragments: {} ({} small)",
+                    stats.num_fragments, stats.num_small_fragments
+                );
+                println!("  Versions: {}", stats.num_versions);
+                println!(
+                    "  On-disk size: {:.1} MB",
+                    stats.disk_bytes as f64 / 1_048_576.0
+                );
+
+                println!("\nRows per source:");
+                for (source, rows) in &stats.rows_per_source {
+                    println!("  {} - {}", source, rows);
+                }
+
+                println!("\nIndices:");
+                if stats.indices.is_empty() {
+                    println!("  (none)");
+                }
+                for index in &stats.indices {
+                    println!(
+                        "  {} on [{}] ({}): {} indexed, {} unindexed",
+                        index.name,
+                        index.columns.join(", "),
+                        index.index_type,
+                        index.indexed_rows,
+                        index.unindexed_rows
+                    );
+                }
+            }
+        }
+        StorageCommands::Versions { json } => {
+            let versions = store.versions().await?;
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&versions)?);
+            } else {
+                println!("Table versions:");
+                for version in &versions {
+                    let latest = if version.is_latest { " (latest)" } else { "" };
+                    let tags = if version.tags.is_empty() {
+                        String::new()
+                    } else {
+                        format!(" [{}]", version.tags.join(", "))
+                    };
+                    println!(
+                        "  {:>6}  {}{}{}",
+                        version.version, version.timestamp, tags, latest
+                    );
+                }
+            }
+        }
+        StorageCommands::Rollback { target } => {
+            let restored = store.rollback(&target).await?;
+            println!("Restored version {} ({})", restored, target);
+
+            // Commits indexed after the restored version are no longer in the table.
+            let scheduler = Scheduler::new(
+                config.data_dir().join(&config.scheduler.metadata_file),
+                config.scheduler.check_interval_hours,
+                config.scheduler.download_window_start,
+                config.scheduler.download_window_end,
+                &config.scheduler.timezone,
+            )?;
+            let mut metadata_store = scheduler.load_metadata()?;
+            let reset = metadata_store.reset_commits();
+            scheduler.save_metadata(&metadata_store)?;
+            if reset > 0 {
+                println!(
+                    "Reset the indexed commit of {} sources; the next `brain update run` re-indexes them in full",
+                    reset
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn handle_adopt_legacy(config: &Config, model: &str) -> Result<()> {
+    let db_path = config.data_dir().join(&config.storage.lancedb_path);
+    let table = &config.storage.table_name;
+
+    let fingerprint = VectorStore::adopt_legacy(&db_path.join(table), table, model).await?;
+    println!(
+        "Recorded {} as built with {} ({} dims, {} pooling)",
+        table, fingerprint.model_id, fingerprint.dimensions, fingerprint.pooling
+    );
+    Ok(())
+}
+
+async fn handle_migrate_embeddings(
+    config: &Config,
+    model: &str,
+    table: Option<String>,
+    batch_size: usize,
+) -> Result<()> {
+    let db_path = config.data_dir().join(&config.storage.lancedb_path);
+    let source_table = &config.storage.table_name;
+
+    let source = VectorStore::open_existing(
+        &db_path.join(source_table),
+        source_table,
+        config.storage.index.clone(),
+    )
+    .await?;
+
+    println!("Loading embedding model: {}", model);
+    let embedder = create_embedder(&config.embedding.for_model(model)).await?;
+    let fingerprint = embedder.fingerprint();
+
+    if &fingerprint == source.fingerprint() {
+        anyhow::bail!("Table {} is already embedded with {}", source_table, model);
+    }
+
+    let target_table = table.unwrap_or_else(|| {
+        let model_name: String = model
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        format!("{}_{}", source_table, model_name.to_lowercase())
+    });
+    if &target_table == source_table {
+        anyhow::bail!("Target table must differ from {}", source_table);
+    }
+
+    // Creating the target checks its fingerprint, so resuming with another model fails.
+    let target = VectorStore::new(
+        &db_path.join(&target_table),
+        &target_table,
+        fingerprint.clone(),
+        config.storage.index.clone(),
+    )
+    .await?;
+
+    let total = source.count().await?;
+    let done = target.ids().await?;
+    if !done.is_empty() {
+        println!(
+            "Resuming migration into {}: {}/{} chunks already migrated",
+            target_table,
+            done.len(),
+            total
+        );
+    }
+
+    println!(
+        "Re-embedding {} chunks from {} ({}, {} dims) into {} ({}, {} dims)",
+        total,
+        source_table,
+        source.fingerprint().model_id,
+        source.fingerprint().dimensions,
+        target_table,
+        fingerprint.model_id,
+        fingerprint.dimensions
+    );
+
+    let mut offset = 0;
+    let mut migrated = 0;
+    while offset < total {
+        let documents = source.read_documents(offset, batch_size).await?;
+        if documents.is_empty() {
+            break;
+        }
+        offset += documents.len();
+
+        let mut batch: Vec<_> = documents
+            .into_iter()
+            .filter(|doc| !done.contains(&doc.id))
+            .collect();
+        let texts: Vec<String> = batch
+            .iter()
+            .map(|doc| embedding_text(&doc.content, doc.heading.as_deref()))
+            .collect();
+        for (doc, embedding) in batch
+            .iter_mut()
+            .zip(embedder.embed_documents(&texts).await?)
+        {
+            doc.embedding = embedding;
+        }
+
+        migrated += batch.len();
+        target.insert(batch).await?;
+        println!("  {}/{} chunks", offset, total);
+    }
+
+    let source_ids = source.ids().await?;
+    let target_ids = target.ids().await?;
+    if source_ids != target_ids {
+        anyhow::bail!(
+            "Verification failed: {} has {} chunks, {} has {} ({} missing, {} unexpected). \
+             Re-run the migration to resume.",
+            source_table,
+            source_ids.len(),
+            target_table,
+            target_ids.len(),
+            source_ids.difference(&target_ids).count(),
+            target_ids.difference(&source_ids).count()
+        );
+    }
+
+    target.update_indices().await?;
+
+    Config::switch_embedding(model, fingerprint.dimensions, &target_table)?;
+
+    println!(
+        "Migrated {} chunks ({} embedded in this run).",
+        target_ids.len(),
+        migrated
+    );
+    println!(
+        "Updated {}: embedding.model = {}, storage.table_name = {}",
+        Config::path().display(),
+        model,
+        target_table
+    );
+    println!(
+        "The old table is kept at {}; delete it once the new one is verified.",
+        db_path.join(source_table).display()
+    );
+
+    Ok(())
+}
+
+async fn handle_bench(config: &Config, action: BenchCommands) -> Result<()> {
+    match action {
+        BenchCommands::Embed {
+            corpus,
+            chunks,
+            dtypes,
+        } => {
+            let mut texts: Vec<String> = Vec::new();
+
+            for dtype in dtypes {
+                let embedder = create_embedder(&EmbeddingConfig {
+                    dtype,
+                    ..config.embedding.clone()
+                })
+                .await?;
+
+                // Chunk the corpus as indexing would; tokenization does not depend on the dtype.
+                if texts.is_empty() {
+                    texts = bench_texts(config, &corpus, embedder.as_ref(), chunks)?;
+                    println!(
+                        "Embedding {} chunks from {} with {}",
+                        texts.len(),
+                        corpus.display(),
+                        config.embedding.model
+                    );
+                    println!(
+                        "{:<6} {:<24} {:>12} {:>14} {:>14}",
+                        "dtype", "device", "chunks/sec", "peak RSS MiB", "peak GPU MiB"
+                    );
+                }
+
+                // Measure from here, with the model loaded, so the peak is the
+                // model plus what embedding allocates.
+                reset_peak_rss();
+
+                // Warm up kernels and allocator before timing.
+                let warmup = texts.len().min(config.embedding.batch_size);
+                embedder.embed_documents(&texts[..warmup]).await?;
+
+                let device = embedder.device();
+                let stop = Arc::new(AtomicBool::new(false));
+                let sampler = device
+                    .starts_with("cuda")
+                    .then(|| sample_gpu_memory(stop.clone()));
+
+                let start = std::time::Instant::now();
+                let result = embedder.embed_documents(&texts).await;
+                let elapsed = start.elapsed().as_secs_f64();
+
+                stop.store(true, Ordering::Relaxed);
+                let gpu = sampler
+                    .and_then(|sampler| sampler.join().ok().flatten())
+                    .map_or("-".to_string(), |mib| mib.to_string());
+                result?;
+
+                println!(
+                    "{:<6} {:<24} {:>12.1} {:>14} {:>14}",
+                    dtype.to_string(),
+                    device,
+                    texts.len() as f64 / elapsed,
+                    peak_rss_mib().map_or("-".to_string(), |mib| mib.to_string()),
+                    gpu
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The texts of `corpus` as indexing with `embedder` would chunk and embed them.
+fn bench_texts(
+    config: &Config,
+    corpus: &std::path::Path,
+    embedder: &dyn Embedder,
+    chunks: usize,
+) -> Result<Vec<String>> {
+    let loader = LocalLoader::with_chunker(chunker_for(
+        embedder,
+        config.brain.chunk_size,
+        config.brain.chunk_overlap,
+    ));
+    let documents = if corpus.is_file() {
+        loader.load_file(corpus)?
+    } else {
+        loader.load_directory(corpus)?
+    };
+
+    let texts: Vec<String> = documents
+        .into_iter()
+        .flat_map(|(_, _, file_chunks)| file_chunks)
+        .map(|chunk| embedding_text(&chunk.content, chunk.heading.as_deref()))
+        .take(chunks)
+        .collect();
+    if texts.is_empty() {
+        anyhow::bail!("No chunks found in {}", corpus.display());
+    }
+    Ok(texts)
+}
+
+// Peak RSS comes from /proc and is Linux-only; elsewhere it is reported as "-".
+fn reset_peak_rss() {
+    let _ = std::fs::write("/proc/self/clear_refs", "5");
+}
+
+fn peak_rss_mib() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmHWM:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib / 1024)
+}
+
+/// Polls the GPU memory of this process until `stop` is set and returns the
+/// highest reading, or `None` if nvidia-smi never reported it.
+fn sample_gpu_memory(stop: Arc<AtomicBool>) -> std::thread::JoinHandle<Option<u64>> {
+    std::thread::spawn(move || {
+        let mut peak = None;
+        loop {
+            let done = stop.load(Ordering::Relaxed);
+            peak = peak.max(gpu_memory_mib());
+            if done {
+                return peak;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+    })
+}
+
+/// GPU memory held by this process, as reported by nvidia-smi.
+fn gpu_memory_mib() -> Option<u64> {
+    let output = std::process::Command::new("nvidia-smi")
+        .args([
+            "--query-compute-apps=pid,used_memory",
+            "--format=csv,noheader,nounits",
+        ])
+        .output()
+        .ok()?;
+    let pid = std::process::id().to_string();
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| {
+            let (line_pid, used) = line.split_once(',')?;
+            (line_pid.trim() == pid).then(|| used.trim().parse().ok())?
+        })
+}
+
+fn handle_cache(config: &Config, action: CacheCommands) -> Result<()> {
+    let cache = EmbeddingCache::open(
+        &config.data_dir().join(&config.embedding.cache.path),
+        config.embedding.cache.max_size_mb,
+    )?;
+
+    match action {
+        CacheCommands::Clear => {
+            let removed = cache.clear()?;
+            println!("Cleared {} cached embeddings", removed);
+        }
+    }
+
+    Ok(())
+}
+
 async fn handle_status(config: &Config) -> Result<()> {
     let pipeline = init_pipeline(config).await?;
     let count = pipeline.vector_store().count().await?;
@@ -616,6 +1246,24 @@ async fn handle_status(config: &Config) -> Result<()> {
     println!("  Documents: {}", count);
     println!("  Sources: {}", sources.len());
     println!("  Embedding model: {}", config.embedding.model);
+    println!("  Embedding device: {}", pipeline.embedder().device());
+    if config.embedding.cache.enabled {
+        let cache = EmbeddingCache::open(
+            &config.data_dir().join(&config.embedding.cache.path),
+            config.embedding.cache.max_size_mb,
+        )?
+        .stats()?;
+        println!(
+            "  Embedding cache: {} entries, {:.1} MiB, {:.1}% hit rate ({} hits, {} misses)",
+            cache.entries,
+            cache.size_bytes as f64 / (1024.0 * 1024.0),
+            cache.hit_rate() * 100.0,
+            cache.hits,
+            cache.misses
+        );
+    } else {
+        println!("  Embedding cache: disabled");
+    }
     println!("  LLM endpoint: {}", config.llm.base_url);
 
     Ok(())
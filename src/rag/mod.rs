@@ -2,4 +2,4 @@ pub mod client;
 pub mod pipeline;
 
 pub use client::MistralRsClient;
-pub use pipeline::{QueryResponse, RagPipeline, SourceInfo};
+pub use pipeline::{IndexStats, Prune, QueryResponse, RagPipeline, SourceInfo, chunker_for};
@@ -1,45 +1,323 @@
-use crate::embedding::EmbeddingModel;
+use crate::embedding::Embedder;
+use crate::loaders::chunker::{Chunk, TextChunker, embedding_text};
 use crate::rag::MistralRsClient;
 use crate::rag::client::Message;
-use crate::storage::{DocumentWithEmbedding, SearchResult, VectorStore};
+use crate::storage::{
+    DocumentWithEmbedding, SearchMode, SearchOptions, SearchResult, SourceType, VectorStore,
+    chunk_id, file_hash,
+};
 use anyhow::Result;
 use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/// A chunker for `embedder`. With a local model, sizes are counted in its
+/// tokens and capped so every chunk fits its input together with the document
+/// prefix; otherwise they are counted in words.
+pub fn chunker_for(
+    embedder: &dyn Embedder,
+    chunk_size: usize,
+    chunk_overlap: usize,
+) -> TextChunker {
+    match embedder.document_tokenizer() {
+        Some((tokenizer, budget)) => {
+            if chunk_size > budget {
+                tracing::debug!(
+                    "chunk_size {} exceeds the {} tokens {} accepts per document; using {}",
+                    chunk_size,
+                    budget,
+                    embedder.model_id(),
+                    budget
+                );
+            }
+            TextChunker::with_tokenizer(tokenizer, chunk_size.min(budget), chunk_overlap)
+        }
+        None => TextChunker::new(chunk_size, chunk_overlap),
+    }
+}
 
 pub struct RagPipeline {
-    embedding_model: EmbeddingModel,
+    embedder: Box<dyn Embedder>,
     vector_store: VectorStore,
     llm_client: MistralRsClient,
+    split_overflow: bool,
+    /// Sizes chunks against the whole model input, to catch those that overflow it.
+    fitter: Option<TextChunker>,
 }
 
 impl RagPipeline {
     pub fn new(
-        embedding_model: EmbeddingModel,
+        embedder: Box<dyn Embedder>,
         vector_store: VectorStore,
         llm_client: MistralRsClient,
     ) -> Self {
+        let fitter = embedder
+            .document_tokenizer()
+            .map(|(tokenizer, budget)| TextChunker::with_tokenizer(tokenizer, budget, 0));
         Self {
-            embedding_model,
+            embedder,
             vector_store,
             llm_client,
+            split_overflow: false,
+            fitter,
         }
     }
 
-    pub fn embed(&self, text: &str) -> Result<Vec<f32>> {
-        self.embedding_model.embed_one(text)
+    /// Splits chunks longer than the model input into extra chunks instead of truncating them.
+    pub fn with_split_overflow(mut self, split_overflow: bool) -> Self {
+        self.split_overflow = split_overflow;
+        self
+    }
+
+    /// A chunker for this pipeline's embedding model; see [`chunker_for`].
+    pub fn chunker(&self, chunk_size: usize, chunk_overlap: usize) -> TextChunker {
+        chunker_for(self.embedder.as_ref(), chunk_size, chunk_overlap)
+    }
+
+    pub async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        Ok(self
+            .embedder
+            .embed_documents(&[text.to_string()])
+            .await?
+            .remove(0))
+    }
+
+    /// Fills in the embeddings of `documents` that have none in batched forward passes.
+    async fn embed_pending(&self, documents: &mut [DocumentWithEmbedding]) -> Result<()> {
+        let pending: Vec<usize> = documents
+            .iter()
+            .enumerate()
+            .filter(|(_, doc)| doc.embedding.is_empty())
+            .map(|(i, _)| i)
+            .collect();
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let texts: Vec<String> = pending
+            .iter()
+            .map(|&i| embedding_text(&documents[i].content, documents[i].heading.as_deref()))
+            .collect();
+        let embeddings = self.embedder.embed_documents(&texts).await?;
+
+        for (i, embedding) in pending.into_iter().zip(embeddings) {
+            documents[i].embedding = embedding;
+        }
+        Ok(())
     }
 
     pub async fn insert_batch(&self, documents: Vec<DocumentWithEmbedding>) -> Result<()> {
         self.vector_store.insert(documents).await
     }
 
-    pub async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchResult>> {
-        let query_embedding = self.embedding_model.embed_one(query)?;
-        let results = self.vector_store.search(&query_embedding, limit).await?;
+    /// Indexes `documents` for `source`, embedding only new or changed chunks.
+    ///
+    /// Files whose hash matches the stored one are skipped, and files selected by
+    /// `prune` are deleted. The table version before the first write is tagged so
+    /// the run can be rolled back.
+    pub async fn index_documents(
+        &self,
+        source: &str,
+        source_type: SourceType,
+        documents: Vec<(String, String, Vec<Chunk>)>,
+        prune: Prune,
+    ) -> Result<IndexStats> {
+        let existing = self.vector_store.file_hashes(source).await?;
+        let mut stats = IndexStats::default();
+        let mut seen = HashSet::new();
+        let mut batch = Vec::new();
+        let mut batch_files = Vec::new();
+
+        for (_source_name, file_path, chunks) in documents {
+            seen.insert(file_path.clone());
+
+            let texts: Vec<String> = chunks
+                .iter()
+                .map(|c| embedding_text(&c.content, c.heading.as_deref()))
+                .collect();
+            let hash = file_hash(texts.iter().map(String::as_str));
+            if existing.get(&file_path) == Some(&hash) {
+                stats.files_unchanged += 1;
+                stats.chunks_unchanged += chunks.len();
+                continue;
+            }
+
+            let mut previous = if existing.contains_key(&file_path) {
+                self.vector_store
+                    .file_embeddings(source, &file_path)
+                    .await?
+            } else {
+                HashMap::new()
+            };
+
+            let chunks = self.fit_chunks(chunks, &mut stats);
+            let created_at = chrono::Utc::now().to_rfc3339();
+            let mut occurrences: HashMap<String, usize> = HashMap::new();
+
+            for chunk in &chunks {
+                // Ids follow the embedded text, so a renamed heading re-embeds its chunks.
+                let text = embedding_text(&chunk.content, chunk.heading.as_deref());
+                let occurrence = occurrences.entry(text.clone()).or_insert(0);
+                let id = chunk_id(source, &file_path, &text, *occurrence);
+                *occurrence += 1;
+
+                // Chunks without a reusable embedding are embedded in batches on flush.
+                let embedding = match previous.remove(&id) {
+                    Some(embedding) => {
+                        stats.chunks_reused += 1;
+                        embedding
+                    }
+                    None => {
+                        stats.chunks_embedded += 1;
+                        Vec::new()
+                    }
+                };
+
+                batch.push(DocumentWithEmbedding {
+                    id,
+                    content: chunk.content.clone(),
+                    source: source.to_string(),
+                    source_type: source_type.to_string(),
+                    file_path: file_path.clone(),
+                    file_hash: hash.clone(),
+                    chunk_index: chunk.index as i64,
+                    start_line: Some(chunk.start_line as i64),
+                    end_line: Some(chunk.end_line as i64),
+                    start_byte: Some(chunk.start_byte as i64),
+                    end_byte: Some(chunk.end_byte as i64),
+                    symbol: chunk.symbol.clone(),
+                    heading: chunk.heading.clone(),
+                    created_at: created_at.clone(),
+                    embedding,
+                });
+            }
+
+            stats.chunks_deleted += previous.len();
+            if existing.contains_key(&file_path) {
+                stats.files_modified += 1;
+            } else {
+                stats.files_added += 1;
+            }
+            batch_files.push(file_path);
+
+            if batch.len() >= 100 {
+                self.embed_pending(&mut batch).await?;
+                self.ensure_snapshot(source, &mut stats).await?;
+                self.vector_store
+                    .replace_files(source, &batch_files, std::mem::take(&mut batch))
+                    .await?;
+                batch_files.clear();
+                tracing::info!(
+                    "Indexed {} files ({} chunks embedded)...",
+                    stats.files_added + stats.files_modified,
+                    stats.chunks_embedded
+                );
+            }
+        }
+
+        if !batch_files.is_empty() {
+            self.embed_pending(&mut batch).await?;
+            self.ensure_snapshot(source, &mut stats).await?;
+            self.vector_store
+                .replace_files(source, &batch_files, batch)
+                .await?;
+        }
+
+        let removed: Vec<String> = match prune {
+            Prune::Missing => existing
+                .keys()
+                .filter(|path| !seen.contains(*path))
+                .cloned()
+                .collect(),
+            Prune::Files(paths) => paths
+                .into_iter()
+                .filter(|path| existing.contains_key(path) && !seen.contains(path))
+                .collect(),
+        };
+
+        if !removed.is_empty() {
+            self.ensure_snapshot(source, &mut stats).await?;
+            self.vector_store.delete_files(source, &removed).await?;
+            stats.files_deleted = removed.len();
+        }
+
+        if stats.files_added + stats.files_modified + stats.files_deleted > 0 {
+            self.vector_store.update_indices().await?;
+        }
+
+        if stats.chunks_truncated > 0 {
+            tracing::warn!(
+                "{} chunks of {} exceed the embedding model's input and were truncated; \
+                 set embedding.overflow = \"split\" to embed them whole",
+                stats.chunks_truncated,
+                source
+            );
+        }
+
+        tracing::info!(
+            "Indexed {}: {} added, {} modified, {} deleted, {} unchanged files; {} chunks embedded, {} reused",
+            source,
+            stats.files_added,
+            stats.files_modified,
+            stats.files_deleted,
+            stats.files_unchanged,
+            stats.chunks_embedded,
+            stats.chunks_reused
+        );
+
+        Ok(stats)
+    }
+
+    /// Counts chunks that overflow the model input and, when splitting, replaces
+    /// each with pieces that fit.
+    fn fit_chunks(&self, chunks: Vec<Chunk>, stats: &mut IndexStats) -> Vec<Chunk> {
+        let Some(fitter) = &self.fitter else {
+            return chunks;
+        };
+        let oversized = chunks.iter().filter(|chunk| !fitter.fits(chunk)).count();
+        if oversized == 0 {
+            return chunks;
+        }
+
+        if self.split_overflow {
+            stats.chunks_split += oversized;
+            fitter.split_oversized(chunks)
+        } else {
+            stats.chunks_truncated += oversized;
+            chunks
+        }
+    }
+
+    async fn ensure_snapshot(&self, source: &str, stats: &mut IndexStats) -> Result<()> {
+        if stats.snapshot.is_none() {
+            stats.snapshot = Some(self.vector_store.tag_run(source).await?);
+        }
+        Ok(())
+    }
+
+    pub async fn search(&self, query: &str, options: &SearchOptions) -> Result<Vec<SearchResult>> {
+        let mut results = match options.mode {
+            SearchMode::Vector => {
+                let query_embedding = self.embedder.embed_query(query).await?;
+                self.vector_store.search(&query_embedding, options).await?
+            }
+            SearchMode::Fts => self.vector_store.search_fts(query, options).await?,
+            SearchMode::Hybrid => {
+                let query_embedding = self.embedder.embed_query(query).await?;
+                self.vector_store
+                    .search_hybrid(query, &query_embedding, options)
+                    .await?
+            }
+        };
+
+        if let Some(min_score) = options.min_score {
+            results.retain(|r| r.similarity >= min_score);
+        }
+
         Ok(results)
     }
 
-    pub async fn query(&self, query: &str, context_limit: usize) -> Result<String> {
-        let results = self.search(query, context_limit).await?;
+    pub async fn query(&self, query: &str, options: &SearchOptions) -> Result<String> {
+        let results = self.search(query, options).await?;
 
         if results.is_empty() {
             return self.llm_client.complete(query).await;
@@ -48,7 +326,10 @@ impl RagPipeline {
         let context = results
             .iter()
             .enumerate()
-            .map(|(i, r)| format!("[Context {} - {}]:\n{}\n", i + 1, r.file_path, r.content))
+            .map(|(i, r)| {
+                let text = embedding_text(&r.content, r.heading.as_deref());
+                format!("[Context {} - {}]:\n{}\n", i + 1, r.location(), text)
+            })
             .collect::<Vec<_>>()
             .join("\n---\n");
 
@@ -79,15 +360,22 @@ Always cite which context(s) you used in your answer."#;
     pub async fn query_with_sources(
         &self,
         query: &str,
-        context_limit: usize,
+        options: &SearchOptions,
     ) -> Result<QueryResponse> {
-        let results = self.search(query, context_limit).await?;
+        let results = self.search(query, options).await?;
 
         let sources: Vec<SourceInfo> = results
             .iter()
             .map(|r| SourceInfo {
                 source: r.source.clone(),
                 file_path: r.file_path.clone(),
+                start_line: r.start_line,
+                end_line: r.end_line,
+                start_byte: r.start_byte,
+                end_byte: r.end_byte,
+                symbol: r.symbol.clone(),
+                heading: r.heading.clone(),
+                similarity: r.similarity,
                 content_preview: r.content.chars().take(200).collect(),
             })
             .collect();
@@ -98,7 +386,10 @@ Always cite which context(s) you used in your answer."#;
             let context = results
                 .iter()
                 .enumerate()
-                .map(|(i, r)| format!("[Context {} - {}]:\n{}\n", i + 1, r.file_path, r.content))
+                .map(|(i, r)| {
+                    let text = embedding_text(&r.content, r.heading.as_deref());
+                    format!("[Context {} - {}]:\n{}\n", i + 1, r.location(), text)
+                })
                 .collect::<Vec<_>>()
                 .join("\n---\n");
 
@@ -129,11 +420,47 @@ Always cite which context(s) you used in your answer."#;
         Ok(QueryResponse { answer, sources })
     }
 
+    pub fn embedder(&self) -> &dyn Embedder {
+        self.embedder.as_ref()
+    }
+
     pub fn vector_store(&self) -> &VectorStore {
         &self.vector_store
     }
 }
 
+/// Which previously indexed files of a source an indexing run deletes.
+pub enum Prune {
+    /// Files not present in the documents being indexed.
+    Missing,
+    /// Only these files.
+    Files(Vec<String>),
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct IndexStats {
+    /// Tag of the table version before this run's first write.
+    pub snapshot: Option<String>,
+    pub files_added: usize,
+    pub files_modified: usize,
+    pub files_deleted: usize,
+    pub files_unchanged: usize,
+    pub chunks_embedded: usize,
+    pub chunks_reused: usize,
+    pub chunks_unchanged: usize,
+    pub chunks_deleted: usize,
+    /// Chunks longer than the model input whose tail was not embedded.
+    pub chunks_truncated: usize,
+    /// Chunks longer than the model input that were split to fit.
+    pub chunks_split: usize,
+}
+
+impl IndexStats {
+    pub fn total_chunks(&self) -> usize {
+        self.chunks_embedded + self.chunks_reused + self.chunks_unchanged
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct QueryResponse {
     pub answer: String,
@@ -144,5 +471,174 @@ pub struct QueryResponse {
 pub struct SourceInfo {
     pub source: String,
     pub file_path: String,
+    pub start_line: Option<usize>,
+    pub end_line: Option<usize>,
+    pub start_byte: Option<usize>,
+    pub end_byte: Option<usize>,
+    pub symbol: Option<String>,
+    pub heading: Option<String>,
+    pub similarity: f32,
     pub content_preview: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::VectorIndexConfig;
+    use crate::embedding::hash::HashEmbedder;
+
+    fn hash_embedder() -> HashEmbedder {
+        let config = toml::from_str(
+            "provider = \"hash\"\nmodel = \"hash\"\ndimensions = 16\nmax_length = 512",
+        )
+        .unwrap();
+        HashEmbedder::new(&config)
+    }
+
+    fn word_tokenizer() -> tokenizers::Tokenizer {
+        use tokenizers::models::wordlevel::WordLevel;
+        use tokenizers::pre_tokenizers::whitespace::Whitespace;
+
+        let vocab = HashMap::from([("[UNK]".to_string(), 0)]);
+        let model = WordLevel::builder()
+            .vocab(vocab.into_iter().collect())
+            .unk_token("[UNK]".to_string())
+            .build()
+            .unwrap();
+        let mut tokenizer = tokenizers::Tokenizer::new(model);
+        tokenizer.with_pre_tokenizer(Some(Whitespace));
+        tokenizer
+    }
+
+    /// The hash embedder with an input of `limit` words.
+    struct WordLimit {
+        inner: HashEmbedder,
+        limit: usize,
+    }
+
+    #[async_trait::async_trait]
+    impl Embedder for WordLimit {
+        async fn embed_documents(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+            self.inner.embed_documents(texts).await
+        }
+
+        async fn embed_query(&self, query: &str) -> Result<Vec<f32>> {
+            self.inner.embed_query(query).await
+        }
+
+        fn dimensions(&self) -> usize {
+            self.inner.dimensions()
+        }
+
+        fn model_id(&self) -> &str {
+            self.inner.model_id()
+        }
+
+        fn fingerprint(&self) -> crate::embedding::ModelFingerprint {
+            self.inner.fingerprint()
+        }
+
+        fn device(&self) -> String {
+            self.inner.device()
+        }
+
+        fn document_tokenizer(&self) -> Option<(tokenizers::Tokenizer, usize)> {
+            Some((word_tokenizer(), self.limit))
+        }
+    }
+
+    async fn pipeline(dir: &std::path::Path, embedder: Box<dyn Embedder>) -> RagPipeline {
+        let store = VectorStore::new(
+            dir,
+            "documents",
+            embedder.fingerprint(),
+            VectorIndexConfig::default(),
+        )
+        .await
+        .unwrap();
+        let llm = MistralRsClient::new("http://127.0.0.1:9", "none", 1, 0.0);
+        RagPipeline::new(embedder, store, llm)
+    }
+
+    async fn hash_pipeline(dir: &std::path::Path) -> RagPipeline {
+        pipeline(dir, Box::new(hash_embedder())).await
+    }
+
+    async fn index(pipeline: &RagPipeline, text: &str) -> IndexStats {
+        let chunks = pipeline.chunker(100, 0).chunk_document(text, "md");
+        let documents = vec![("local:test".to_string(), "notes.md".to_string(), chunks)];
+        pipeline
+            .index_documents("local:test", SourceType::Local, documents, Prune::Missing)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn reindexing_reuses_unchanged_chunks_at_their_new_location() {
+        let dir = std::env::temp_dir().join(format!("brain-index-{}", uuid::Uuid::new_v4()));
+        let pipeline = hash_pipeline(&dir).await;
+
+        let first = "# Alpha\n\nalpha one.\n\n# Beta\n\nbeta two.\n";
+        let stats = index(&pipeline, first).await;
+        assert_eq!((stats.files_added, stats.chunks_embedded), (1, 2));
+
+        let stats = index(&pipeline, first).await;
+        assert_eq!((stats.files_unchanged, stats.chunks_unchanged), (1, 2));
+
+        let second = "Intro.\n\n# Alpha\n\nalpha one.\n\n# Beta\n\nbeta three.\n";
+        let stats = index(&pipeline, second).await;
+        assert_eq!(stats.files_modified, 1);
+        assert_eq!(stats.chunks_reused, 1);
+        assert_eq!(stats.chunks_embedded, 2);
+        assert_eq!(stats.chunks_deleted, 1);
+
+        let mut rows = pipeline.vector_store().read_documents(0, 10).await.unwrap();
+        rows.sort_by_key(|row| row.chunk_index);
+        let locations: Vec<_> = rows
+            .iter()
+            .map(|row| {
+                let bytes = row.start_byte.unwrap() as usize..row.end_byte.unwrap() as usize;
+                assert_eq!(row.content, second[bytes]);
+                (row.start_line.unwrap(), row.end_line.unwrap())
+            })
+            .collect();
+        assert_eq!(locations, [(1, 1), (3, 5), (7, 9)]);
+        assert_eq!(rows[1].heading.as_deref(), Some("Alpha"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn split_chunks_fit_with_their_heading_path() {
+        let dir = std::env::temp_dir().join(format!("brain-fit-{}", uuid::Uuid::new_v4()));
+        let embedder = WordLimit {
+            inner: hash_embedder(),
+            limit: 6,
+        };
+        let pipeline = pipeline(&dir, Box::new(embedder))
+            .await
+            .with_split_overflow(true);
+
+        let chunk = Chunk {
+            content: "a b c d e f g h".to_string(),
+            index: 0,
+            start_line: 3,
+            end_line: 3,
+            start_byte: 10,
+            end_byte: 25,
+            symbol: None,
+            heading: Some("One two three".to_string()),
+        };
+        let mut stats = IndexStats::default();
+        let chunks = pipeline.fit_chunks(vec![chunk], &mut stats);
+
+        assert_eq!(stats.chunks_split, 1);
+        let pieces: Vec<_> = chunks
+            .iter()
+            .map(|c| (c.content.trim(), c.start_byte, c.index))
+            .collect();
+        assert_eq!(pieces, [("a b c", 10, 0), ("d e f", 16, 1), ("g h", 22, 2)]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -51,6 +51,15 @@ impl MetadataStore {
     pub fn upsert(&mut self, metadata: SourceMetadata) {
         self.sources.insert(metadata.source.clone(), metadata);
     }
+
+    /// Forgets the indexed commit of every source, so the next update re-indexes
+    /// each one in full. Returns the number of sources reset.
+    pub fn reset_commits(&mut self) -> usize {
+        self.sources
+            .values_mut()
+            .filter_map(|metadata| metadata.last_commit_hash.take())
+            .count()
+    }
 }
 
 pub struct Scheduler {
@@ -142,6 +151,44 @@ impl Scheduler {
         Ok(commit.id().to_string())
     }
 
+    pub fn changes_since(repo_path: &std::path::Path, from_commit: &str) -> Result<RepoChanges> {
+        let repo = Repository::open(repo_path)?;
+        let old_tree = repo
+            .find_commit(git2::Oid::from_str(from_commit)?)?
+            .tree()?;
+        let new_tree = repo.head()?.peel_to_tree()?;
+
+        let mut diff = repo.diff_tree_to_tree(Some(&old_tree), Some(&new_tree), None)?;
+        let mut find_options = git2::DiffFindOptions::new();
+        find_options.renames(true);
+        diff.find_similar(Some(&mut find_options))?;
+
+        let mut changes = RepoChanges::default();
+
+        for delta in diff.deltas() {
+            let old_path = delta
+                .old_file()
+                .path()
+                .map(|p| p.to_string_lossy().to_string());
+            let new_path = delta
+                .new_file()
+                .path()
+                .map(|p| p.to_string_lossy().to_string());
+
+            match (delta.status(), old_path, new_path) {
+                (git2::Delta::Added | git2::Delta::Copied, _, Some(new)) => changes.added.push(new),
+                (git2::Delta::Deleted, Some(old), _) => changes.deleted.push(old),
+                (git2::Delta::Modified | git2::Delta::Typechange, _, Some(new)) => {
+                    changes.modified.push(new)
+                }
+                (git2::Delta::Renamed, Some(old), Some(new)) => changes.renamed.push((old, new)),
+                _ => {}
+            }
+        }
+
+        Ok(changes)
+    }
+
     pub fn has_updates(
         &self,
         metadata: &SourceMetadata,
@@ -219,6 +266,52 @@ pub struct UpdateCheckResult {
     pub in_window: bool,
 }
 
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RepoChanges {
+    pub added: Vec<String>,
+    pub modified: Vec<String>,
+    pub deleted: Vec<String>,
+    pub renamed: Vec<(String, String)>,
+}
+
+impl RepoChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.modified.is_empty()
+            && self.deleted.is_empty()
+            && self.renamed.is_empty()
+    }
+
+    pub fn paths_to_index(&self) -> Vec<String> {
+        self.added
+            .iter()
+            .chain(&self.modified)
+            .chain(self.renamed.iter().map(|(_, new)| new))
+            .cloned()
+            .collect()
+    }
+
+    pub fn paths_to_remove(&self) -> Vec<String> {
+        self.deleted
+            .iter()
+            .chain(self.renamed.iter().map(|(old, _)| old))
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateRunResult {
+    pub source: String,
+    pub from_commit: Option<String>,
+    pub to_commit: String,
+    pub full_reindex: bool,
+    pub snapshot: Option<String>,
+    pub changes: RepoChanges,
+    pub chunks_embedded: usize,
+    pub chunks_reused: usize,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UpdateReport {
     pub checked_at: String,
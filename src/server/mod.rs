@@ -20,6 +20,22 @@ pub struct QueryRequest {
     pub query: String,
     #[serde(default = "default_limit")]
     pub limit: usize,
+    #[serde(default)]
+    pub mode: crate::storage::SearchMode,
+    #[serde(default)]
+    pub filter: crate::storage::SearchFilter,
+    pub min_score: Option<f32>,
+    pub nprobes: Option<usize>,
+    pub refine_factor: Option<u32>,
+}
+
+impl QueryRequest {
+    fn search_options(&self) -> crate::storage::SearchOptions {
+        crate::storage::SearchOptions::new(self.limit, self.mode)
+            .with_filter(self.filter.clone())
+            .with_min_score(self.min_score)
+            .with_ann_params(self.nprobes, self.refine_factor)
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -52,6 +68,7 @@ pub struct ErrorResponse {
 pub struct AddSourceResponse {
     pub source: String,
     pub chunks_indexed: usize,
+    pub chunks_embedded: usize,
 }
 
 pub fn create_router(state: Arc<AppState>) -> Router {
@@ -64,6 +81,7 @@ pub fn create_router(state: Arc<AppState>) -> Router {
         .route("/sources/local", post(add_local_source))
         .route("/sources/{source}", delete(delete_source))
         .route("/status", get(status))
+        .route("/admin/stats", get(storage_stats))
         .layer(CorsLayer::new().allow_origin(Any).allow_methods(Any))
         .with_state(state)
 }
@@ -78,7 +96,7 @@ async fn query(
 ) -> impl IntoResponse {
     match state
         .pipeline
-        .query_with_sources(&req.query, req.limit)
+        .query_with_sources(&req.query, &req.search_options())
         .await
     {
         Ok(response) => Json(response).into_response(),
@@ -96,7 +114,11 @@ async fn search(
     state: axum::extract::State<Arc<AppState>>,
     Json(req): Json<QueryRequest>,
 ) -> impl IntoResponse {
-    match state.pipeline.search(&req.query, req.limit).await {
+    match state
+        .pipeline
+        .search(&req.query, &req.search_options())
+        .await
+    {
         Ok(results) => Json(results).into_response(),
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -140,10 +162,12 @@ async fn add_github_source(
             .into_response();
     }
 
-    let loader = crate::loaders::GitHubLoader::new(
+    let loader = crate::loaders::GitHubLoader::with_chunker(
         repos_path,
-        state.config.brain.chunk_size,
-        state.config.brain.chunk_overlap,
+        state.pipeline.chunker(
+            state.config.brain.chunk_size,
+            state.config.brain.chunk_overlap,
+        ),
     );
 
     let repo_path = match loader.clone_or_update(&req.owner, &req.repo, &req.branch) {
@@ -174,15 +198,17 @@ async fn add_github_source(
 
     let source_name = format!("github:{}/{}", req.owner, req.repo);
 
-    let count = match index_documents(
-        &state.pipeline,
-        &source_name,
-        crate::storage::SourceType::GitHub,
-        documents,
-    )
-    .await
+    let stats = match state
+        .pipeline
+        .index_documents(
+            &source_name,
+            crate::storage::SourceType::GitHub,
+            documents,
+            crate::rag::Prune::Missing,
+        )
+        .await
     {
-        Ok(c) => c,
+        Ok(stats) => stats,
         Err(e) => {
             return (
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -207,7 +233,8 @@ async fn add_github_source(
 
     Json(AddSourceResponse {
         source: source_name,
-        chunks_indexed: count,
+        chunks_indexed: stats.total_chunks(),
+        chunks_embedded: stats.chunks_embedded,
     })
     .into_response()
 }
@@ -228,10 +255,10 @@ async fn add_local_source(
             .into_response();
     }
 
-    let loader = crate::loaders::LocalLoader::new(
+    let loader = crate::loaders::LocalLoader::with_chunker(state.pipeline.chunker(
         state.config.brain.chunk_size,
         state.config.brain.chunk_overlap,
-    );
+    ));
 
     let documents = match loader.load_directory(path) {
         Ok(docs) => docs,
@@ -248,15 +275,17 @@ async fn add_local_source(
 
     let source_name = format!("local:{}", path.display());
 
-    let count = match index_documents(
-        &state.pipeline,
-        &source_name,
-        crate::storage::SourceType::Local,
-        documents,
-    )
-    .await
+    let stats = match state
+        .pipeline
+        .index_documents(
+            &source_name,
+            crate::storage::SourceType::Local,
+            documents,
+            crate::rag::Prune::Missing,
+        )
+        .await
     {
-        Ok(c) => c,
+        Ok(stats) => stats,
         Err(e) => {
             return (
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -270,52 +299,12 @@ async fn add_local_source(
 
     Json(AddSourceResponse {
         source: source_name,
-        chunks_indexed: count,
+        chunks_indexed: stats.total_chunks(),
+        chunks_embedded: stats.chunks_embedded,
     })
     .into_response()
 }
 
-async fn index_documents(
-    pipeline: &crate::rag::RagPipeline,
-    source: &str,
-    source_type: crate::storage::SourceType,
-    documents: Vec<(String, String, Vec<crate::loaders::chunker::Chunk>)>,
-) -> anyhow::Result<usize> {
-    let mut total_indexed = 0;
-    let mut batch = Vec::new();
-
-    for (_source_name, file_path, chunks) in documents {
-        for chunk in chunks {
-            let id = uuid::Uuid::new_v4().to_string();
-            let embedding = pipeline.embed(&chunk.content)?;
-
-            batch.push(crate::storage::DocumentWithEmbedding {
-                id,
-                content: chunk.content.clone(),
-                source: source.to_string(),
-                source_type: source_type.to_string(),
-                file_path: file_path.clone(),
-                chunk_index: chunk.index as i64,
-                created_at: chrono::Utc::now().to_rfc3339(),
-                embedding,
-            });
-
-            total_indexed += 1;
-
-            if batch.len() >= 100 {
-                pipeline.insert_batch(batch.clone()).await?;
-                batch.clear();
-            }
-        }
-    }
-
-    if !batch.is_empty() {
-        pipeline.insert_batch(batch).await?;
-    }
-
-    Ok(total_indexed)
-}
-
 fn save_source_metadata(
     config: &crate::config::Config,
     _source: &str,
@@ -378,6 +367,19 @@ async fn status(state: axum::extract::State<Arc<AppState>>) -> impl IntoResponse
     }
 }
 
+async fn storage_stats(state: axum::extract::State<Arc<AppState>>) -> impl IntoResponse {
+    match state.pipeline.vector_store().stats().await {
+        Ok(stats) => Json(stats).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: e.to_string(),
+            }),
+        )
+            .into_response(),
+    }
+}
+
 pub async fn run_server(state: Arc<AppState>, host: &str, port: u16) -> anyhow::Result<()> {
     let addr: SocketAddr = format!("{}:{}", host, port).parse()?;
     let app = create_router(state);
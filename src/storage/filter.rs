@@ -0,0 +1,259 @@
+use crate::storage::{SourceType, sql_string};
+use anyhow::Result;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SearchFilter {
+    #[serde(default)]
+    pub sources: Vec<String>,
+    #[serde(default)]
+    pub exclude_sources: Vec<String>,
+    pub source_type: Option<SourceType>,
+    pub path_prefix: Option<String>,
+    pub path_glob: Option<String>,
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    #[serde(default)]
+    pub languages: Vec<String>,
+    pub created_after: Option<String>,
+    pub created_before: Option<String>,
+}
+
+impl SearchFilter {
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+            && self.exclude_sources.is_empty()
+            && self.source_type.is_none()
+            && self.path_prefix.is_none()
+            && self.path_glob.is_none()
+            && self.extensions.is_empty()
+            && self.languages.is_empty()
+            && self.created_after.is_none()
+            && self.created_before.is_none()
+    }
+
+    /// Compiles the filter into a LanceDB `only_if` predicate.
+    pub fn to_predicate(&self) -> Result<Option<String>> {
+        let mut clauses = Vec::new();
+
+        if !self.sources.is_empty() {
+            clauses.push(format!("source IN ({})", string_list(&self.sources)));
+        }
+
+        if !self.exclude_sources.is_empty() {
+            clauses.push(format!(
+                "source NOT IN ({})",
+                string_list(&self.exclude_sources)
+            ));
+        }
+
+        if let Some(source_type) = &self.source_type {
+            clauses.push(format!(
+                "source_type = {}",
+                sql_string(&source_type.to_string())
+            ));
+        }
+
+        if let Some(prefix) = &self.path_prefix {
+            clauses.push(format!(
+                "file_path LIKE {}",
+                sql_string(&format!("{}%", escape_like(prefix)))
+            ));
+        }
+
+        if let Some(glob) = &self.path_glob {
+            clauses.push(format!(
+                "file_path LIKE {}",
+                sql_string(&glob_to_like(glob))
+            ));
+        }
+
+        let mut extensions: Vec<String> = self
+            .extensions
+            .iter()
+            .map(|e| e.trim_start_matches('.').to_lowercase())
+            .collect();
+        for language in &self.languages {
+            let language_extensions = language_extensions(language)
+                .ok_or_else(|| anyhow::anyhow!("Unknown language: {}", language))?;
+            extensions.extend(language_extensions.iter().map(|e| e.to_string()));
+        }
+        extensions.sort();
+        extensions.dedup();
+
+        if !extensions.is_empty() {
+            let alternatives = extensions
+                .iter()
+                .map(|e| {
+                    format!(
+                        "file_path LIKE {}",
+                        sql_string(&format!("%.{}", escape_like(e)))
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(" OR ");
+            clauses.push(format!("({})", alternatives));
+        }
+
+        if let Some(after) = &self.created_after {
+            clauses.push(format!(
+                "created_at >= {}",
+                sql_string(&parse_timestamp(after)?)
+            ));
+        }
+
+        if let Some(before) = &self.created_before {
+            clauses.push(format!(
+                "created_at < {}",
+                sql_string(&parse_timestamp(before)?)
+            ));
+        }
+
+        if clauses.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(clauses.join(" AND ")))
+        }
+    }
+}
+
+pub fn language_extensions(language: &str) -> Option<&'static [&'static str]> {
+    let extensions: &[&str] = match language.to_lowercase().as_str() {
+        "rust" => &["rs"],
+        "python" => &["py"],
+        "javascript" | "js" => &["js", "jsx"],
+        "typescript" | "ts" => &["ts", "tsx"],
+        "go" => &["go"],
+        "java" => &["java"],
+        "c" => &["c", "h"],
+        "cpp" | "c++" => &["cpp", "hpp", "h"],
+        "ruby" => &["rb"],
+        "php" => &["php"],
+        "swift" => &["swift"],
+        "kotlin" => &["kt"],
+        "scala" => &["scala"],
+        "lua" => &["lua"],
+        "r" => &["r"],
+        "zig" => &["zig"],
+        "sql" => &["sql"],
+        "shell" | "bash" => &["sh", "bash"],
+        "toml" => &["toml"],
+        "yaml" => &["yaml", "yml"],
+        "json" => &["json"],
+        "markdown" => &["md"],
+        "rst" => &["rst"],
+        "asciidoc" => &["adoc"],
+        "text" => &["txt"],
+        _ => return None,
+    };
+    Some(extensions)
+}
+
+fn string_list(values: &[String]) -> String {
+    values
+        .iter()
+        .map(|v| sql_string(v))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+// `*` and `**` both become `%`, so a single `*` may also match across `/`.
+fn glob_to_like(glob: &str) -> String {
+    let mut pattern = String::new();
+    let mut chars = glob.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => {
+                while chars.peek() == Some(&'*') {
+                    chars.next();
+                }
+                pattern.push('%');
+            }
+            '?' => pattern.push('_'),
+            '\\' | '%' | '_' => {
+                pattern.push('\\');
+                pattern.push(c);
+            }
+            _ => pattern.push(c),
+        }
+    }
+
+    pattern
+}
+
+fn parse_timestamp(value: &str) -> Result<String> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Ok(timestamp.with_timezone(&Utc).to_rfc3339());
+    }
+
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| {
+        anyhow::anyhow!(
+            "Invalid timestamp (expected RFC 3339 or YYYY-MM-DD): {}",
+            value
+        )
+    })?;
+    Ok(date
+        .and_hms_opt(0, 0, 0)
+        .expect("midnight is a valid time")
+        .and_utc()
+        .to_rfc3339())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_like_wildcards() {
+        assert_eq!(escape_like("src/my_mod%\\"), "src/my\\_mod\\%\\\\");
+    }
+
+    #[test]
+    fn converts_globs_to_like_patterns() {
+        assert_eq!(glob_to_like("src/**/*.rs"), "src/%/%.rs");
+        assert_eq!(glob_to_like("test_?.py"), "test\\__.py");
+        assert_eq!(glob_to_like("100%"), "100\\%");
+    }
+
+    #[test]
+    fn compiles_filters_into_one_predicate() {
+        let filter = SearchFilter {
+            sources: vec!["github:o/r".into()],
+            path_prefix: Some("src/it's".into()),
+            extensions: vec![".RS".into()],
+            languages: vec!["rust".into()],
+            created_after: Some("2024-01-02".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            filter.to_predicate().unwrap().unwrap(),
+            "source IN ('github:o/r') AND file_path LIKE 'src/it''s%' \
+             AND (file_path LIKE '%.rs') AND created_at >= '2024-01-02T00:00:00+00:00'"
+        );
+        assert_eq!(SearchFilter::default().to_predicate().unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_unknown_languages_and_dates() {
+        let language = SearchFilter {
+            languages: vec!["klingon".into()],
+            ..Default::default()
+        };
+        assert!(language.to_predicate().is_err());
+
+        let date = SearchFilter {
+            created_before: Some("yesterday".into()),
+            ..Default::default()
+        };
+        assert!(date.to_predicate().is_err());
+    }
+}
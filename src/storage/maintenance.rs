@@ -0,0 +1,323 @@
+use crate::storage::VectorStore;
+use anyhow::Result;
+use arrow_array::{Array, StringArray};
+use futures::StreamExt;
+use lancedb::table::{CompactionOptions, OptimizeAction};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CompactionReport {
+    pub fragments_removed: usize,
+    pub fragments_added: usize,
+    pub files_removed: usize,
+    pub files_added: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CleanupReport {
+    pub versions_removed: u64,
+    pub bytes_removed: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexCoverage {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub index_type: String,
+    pub indexed_rows: usize,
+    pub unindexed_rows: usize,
+}
+
+/// Automatic tags created before indexing runs start with this prefix.
+const RUN_TAG_PREFIX: &str = "index-";
+
+/// Number of automatic run tags kept; older ones are deleted so cleanup can
+/// reclaim their versions.
+const MAX_RUN_TAGS: usize = 10;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VersionInfo {
+    pub version: u64,
+    pub timestamp: String,
+    pub tags: Vec<String>,
+    pub is_latest: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StorageStats {
+    pub table_name: String,
+    pub num_rows: usize,
+    pub num_fragments: usize,
+    pub num_small_fragments: usize,
+    pub num_versions: usize,
+    pub disk_bytes: u64,
+    pub rows_per_source: BTreeMap<String, usize>,
+    pub indices: Vec<IndexCoverage>,
+}
+
+impl VectorStore {
+    /// Rewrites small fragments into larger ones and remaps indices.
+    pub async fn compact(&self) -> Result<CompactionReport> {
+        let table = self.db.open_table(&self.table_name).execute().await?;
+
+        let stats = table
+            .optimize(OptimizeAction::Compact {
+                options: CompactionOptions::default(),
+                remap_options: None,
+            })
+            .await?;
+
+        let metrics = stats.compaction.unwrap_or_default();
+        tracing::info!(
+            "Compacted {}: {} fragments removed, {} added",
+            self.table_name,
+            metrics.fragments_removed,
+            metrics.fragments_added
+        );
+
+        Ok(CompactionReport {
+            fragments_removed: metrics.fragments_removed,
+            fragments_added: metrics.fragments_added,
+            files_removed: metrics.files_removed,
+            files_added: metrics.files_added,
+        })
+    }
+
+    /// Deletes table versions older than `older_than`; tagged versions are kept.
+    pub async fn cleanup(&self, older_than: chrono::Duration) -> Result<CleanupReport> {
+        let table = self.db.open_table(&self.table_name).execute().await?;
+
+        let stats = table
+            .optimize(OptimizeAction::Prune {
+                older_than: Some(older_than),
+                delete_unverified: Some(false),
+                error_if_tagged_old_versions: Some(false),
+            })
+            .await?;
+
+        let removal = stats.prune.unwrap_or_default();
+        tracing::info!(
+            "Pruned {} versions of {} ({} bytes)",
+            removal.old_versions,
+            self.table_name,
+            removal.bytes_removed
+        );
+
+        Ok(CleanupReport {
+            versions_removed: removal.old_versions,
+            bytes_removed: removal.bytes_removed,
+        })
+    }
+
+    pub async fn stats(&self) -> Result<StorageStats> {
+        let table = self.db.open_table(&self.table_name).execute().await?;
+
+        let table_stats = table.stats().await?;
+        let num_versions = table.list_versions().await?.len();
+
+        let mut indices = Vec::new();
+        for index in table.list_indices().await? {
+            let (indexed_rows, unindexed_rows) = match table.index_stats(&index.name).await? {
+                Some(stats) => (stats.num_indexed_rows, stats.num_unindexed_rows),
+                None => (0, table_stats.num_rows),
+            };
+            indices.push(IndexCoverage {
+                name: index.name,
+                columns: index.columns,
+                index_type: format!("{:?}", index.index_type),
+                indexed_rows,
+                unindexed_rows,
+            });
+        }
+
+        Ok(StorageStats {
+            table_name: self.table_name.clone(),
+            num_rows: table_stats.num_rows,
+            num_fragments: table_stats.fragment_stats.num_fragments,
+            num_small_fragments: table_stats.fragment_stats.num_small_fragments,
+            num_versions,
+            disk_bytes: self.disk_usage(),
+            rows_per_source: self.rows_per_source().await?,
+            indices,
+        })
+    }
+
+    pub async fn rows_per_source(&self) -> Result<BTreeMap<String, usize>> {
+        let table = self.db.open_table(&self.table_name).execute().await?;
+
+        let mut stream = table
+            .query()
+            .select(lancedb::query::Select::columns(&["source"]))
+            .execute()
+            .await?;
+
+        let mut counts = BTreeMap::new();
+
+        while let Some(batch) = stream.next().await {
+            let batch = batch?;
+            if let Some(col) = batch.column_by_name("source")
+                && let Some(arr) = col.as_any().downcast_ref::<StringArray>()
+            {
+                for i in 0..arr.len() {
+                    *counts.entry(arr.value(i).to_string()).or_insert(0) += 1;
+                }
+            }
+        }
+
+        Ok(counts)
+    }
+
+    // Includes data files of old versions, which is what cleanup reclaims.
+    fn disk_usage(&self) -> u64 {
+        let table_dir = self.db_path.join(format!("{}.lance", self.table_name));
+
+        walkdir::WalkDir::new(table_dir)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter_map(|e| e.metadata().ok())
+            .filter(|m| m.is_file())
+            .map(|m| m.len())
+            .sum()
+    }
+
+    /// Tags the current table version before an indexing run of `source`.
+    pub async fn tag_run(&self, source: &str) -> Result<String> {
+        let table = self.db.open_table(&self.table_name).execute().await?;
+        let version = table.version().await?;
+
+        let label: String = source
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .collect();
+        let base = format!(
+            "{}{}-{}",
+            RUN_TAG_PREFIX,
+            label,
+            chrono::Utc::now().format("%Y%m%dT%H%M%S%3f")
+        );
+
+        // Runs within the same millisecond get a numbered suffix.
+        let mut tags = table.tags().await?;
+        let existing = tags.list().await?;
+        let mut tag = base.clone();
+        let mut n = 1;
+        while existing.contains_key(&tag) {
+            n += 1;
+            tag = format!("{}-{}", base, n);
+        }
+        tags.create(&tag, version).await?;
+        tracing::info!("Tagged version {} as {}", version, tag);
+
+        let mut run_tags: Vec<(String, u64)> = tags
+            .list()
+            .await?
+            .into_iter()
+            .filter(|(name, _)| name.starts_with(RUN_TAG_PREFIX))
+            .map(|(name, contents)| (name, contents.version))
+            .collect();
+        if run_tags.len() > MAX_RUN_TAGS {
+            run_tags.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+            for (name, _) in &run_tags[..run_tags.len() - MAX_RUN_TAGS] {
+                tags.delete(name).await?;
+            }
+        }
+
+        Ok(tag)
+    }
+
+    pub async fn versions(&self) -> Result<Vec<VersionInfo>> {
+        let table = self.db.open_table(&self.table_name).execute().await?;
+        let latest = table.version().await?;
+
+        let mut tags_by_version: BTreeMap<u64, Vec<String>> = BTreeMap::new();
+        for (name, contents) in table.tags().await?.list().await? {
+            tags_by_version
+                .entry(contents.version)
+                .or_default()
+                .push(name);
+        }
+
+        Ok(table
+            .list_versions()
+            .await?
+            .into_iter()
+            .map(|v| {
+                let mut tags = tags_by_version.remove(&v.version).unwrap_or_default();
+                tags.sort();
+                VersionInfo {
+                    version: v.version,
+                    timestamp: v.timestamp.to_rfc3339(),
+                    tags,
+                    is_latest: v.version == latest,
+                }
+            })
+            .collect())
+    }
+
+    /// Restores a previous version (number or tag) as a new latest version.
+    pub async fn rollback(&self, target: &str) -> Result<u64> {
+        let table = self.db.open_table(&self.table_name).execute().await?;
+
+        match target.parse::<u64>() {
+            Ok(version) => table.checkout(version).await?,
+            Err(_) => table.checkout_tag(target).await?,
+        }
+        let restored = table.version().await?;
+
+        table.restore().await?;
+        let latest = table.version().await?;
+        tracing::info!(
+            "Restored version {} of {} as version {}",
+            restored,
+            self.table_name,
+            latest
+        );
+
+        Ok(restored)
+    }
+}
+
+/// Parses an age such as `30m`, `12h`, `7d` or `2w`.
+pub fn parse_age(value: &str) -> Result<chrono::Duration> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+
+    let amount: i64 = amount
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid age: {} (expected e.g. 7d)", value))?;
+
+    match unit {
+        "s" => Ok(chrono::Duration::seconds(amount)),
+        "m" => Ok(chrono::Duration::minutes(amount)),
+        "h" => Ok(chrono::Duration::hours(amount)),
+        "d" | "" => Ok(chrono::Duration::days(amount)),
+        "w" => Ok(chrono::Duration::weeks(amount)),
+        _ => anyhow::bail!("Invalid age unit: {} (expected s, m, h, d or w)", unit),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ages_with_units() {
+        assert_eq!(parse_age("90s").unwrap(), chrono::Duration::seconds(90));
+        assert_eq!(parse_age("15m").unwrap(), chrono::Duration::minutes(15));
+        assert_eq!(parse_age(" 12h ").unwrap(), chrono::Duration::hours(12));
+        assert_eq!(parse_age("7d").unwrap(), chrono::Duration::days(7));
+        assert_eq!(parse_age("3").unwrap(), chrono::Duration::days(3));
+        assert_eq!(parse_age("2w").unwrap(), chrono::Duration::weeks(2));
+    }
+
+    #[test]
+    fn rejects_malformed_ages() {
+        for value in ["", "d", "7y", "-1d", "1.5h"] {
+            assert!(parse_age(value).is_err(), "{}", value);
+        }
+    }
+}
@@ -1,14 +1,31 @@
+pub mod filter;
+pub mod maintenance;
+
+pub use filter::SearchFilter;
+pub use maintenance::{StorageStats, VersionInfo, parse_age};
+
+use crate::config::{VectorDistance, VectorIndexConfig, VectorIndexType};
+use crate::embedding::ModelFingerprint;
 use anyhow::Result;
 use arrow_array::{
-    Array, FixedSizeListArray, Int64Array, RecordBatch, RecordBatchIterator, StringArray,
-    types::Float32Type,
+    Array, FixedSizeListArray, Float32Array, Int64Array, RecordBatch, RecordBatchIterator,
+    StringArray, types::Float32Type,
 };
 use arrow_schema::{DataType, Field, Schema};
 use futures::StreamExt;
+use lancedb::DistanceType;
+use lancedb::arrow::SendableRecordBatchStream;
 use lancedb::connection::connect;
-use lancedb::query::{ExecutableQuery, QueryBase};
+use lancedb::index::Index;
+use lancedb::index::scalar::{FtsIndexBuilder, FullTextSearchQuery};
+use lancedb::index::vector::{IvfHnswPqIndexBuilder, IvfHnswSqIndexBuilder, IvfPqIndexBuilder};
+use lancedb::query::{ExecutableQuery, QueryBase, Select};
+use lancedb::table::NewColumnTransform;
+use lancedb::table::{OptimizeAction, OptimizeOptions};
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -23,6 +40,7 @@ pub struct Document {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
 pub enum SourceType {
     GitHub,
     Local,
@@ -52,6 +70,106 @@ impl std::str::FromStr for SourceType {
     }
 }
 
+const FTS_COLUMNS: [&str; 2] = ["content", "file_path"];
+
+// Columns added after the initial schema, with the SQL default used to backfill old tables.
+const ADDED_COLUMNS: [(&str, &str); 7] = [
+    ("file_hash", "''"),
+    ("start_line", "CAST(NULL AS BIGINT)"),
+    ("end_line", "CAST(NULL AS BIGINT)"),
+    ("symbol", "CAST(NULL AS VARCHAR)"),
+    ("heading", "CAST(NULL AS VARCHAR)"),
+    ("start_byte", "CAST(NULL AS BIGINT)"),
+    ("end_byte", "CAST(NULL AS BIGINT)"),
+];
+
+const RESULT_COLUMNS: [&str; 13] = [
+    "id",
+    "content",
+    "source",
+    "source_type",
+    "file_path",
+    "chunk_index",
+    "created_at",
+    "start_line",
+    "end_line",
+    "symbol",
+    "heading",
+    "start_byte",
+    "end_byte",
+];
+
+/// Rank offset used by reciprocal rank fusion.
+const RRF_K: f32 = 60.0;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    #[default]
+    Vector,
+    Fts,
+    Hybrid,
+}
+
+impl std::fmt::Display for SearchMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SearchMode::Vector => write!(f, "vector"),
+            SearchMode::Fts => write!(f, "fts"),
+            SearchMode::Hybrid => write!(f, "hybrid"),
+        }
+    }
+}
+
+impl std::str::FromStr for SearchMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "vector" => Ok(SearchMode::Vector),
+            "fts" => Ok(SearchMode::Fts),
+            "hybrid" => Ok(SearchMode::Hybrid),
+            _ => Err(format!("Unknown search mode: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SearchOptions {
+    pub limit: usize,
+    pub mode: SearchMode,
+    pub filter: SearchFilter,
+    pub min_score: Option<f32>,
+    pub nprobes: Option<usize>,
+    pub refine_factor: Option<u32>,
+}
+
+impl SearchOptions {
+    pub fn new(limit: usize, mode: SearchMode) -> Self {
+        Self {
+            limit,
+            mode,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_filter(mut self, filter: SearchFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_min_score(mut self, min_score: Option<f32>) -> Self {
+        self.min_score = min_score;
+        self
+    }
+
+    pub fn with_ann_params(mut self, nprobes: Option<usize>, refine_factor: Option<u32>) -> Self {
+        self.nprobes = nprobes;
+        self.refine_factor = refine_factor;
+        self
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DocumentWithEmbedding {
     pub id: String,
@@ -59,19 +177,99 @@ pub struct DocumentWithEmbedding {
     pub source: String,
     pub source_type: String,
     pub file_path: String,
+    pub file_hash: String,
     pub chunk_index: i64,
+    /// Unknown (NULL) for rows indexed before locations were recorded.
+    pub start_line: Option<i64>,
+    pub end_line: Option<i64>,
+    pub start_byte: Option<i64>,
+    pub end_byte: Option<i64>,
+    pub symbol: Option<String>,
+    pub heading: Option<String>,
     pub created_at: String,
     pub embedding: Vec<f32>,
 }
 
+pub fn sql_string(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Stable chunk id; `occurrence` disambiguates identical chunks within a file.
+pub fn chunk_id(source: &str, file_path: &str, content: &str, occurrence: usize) -> String {
+    let content_hash = Sha256::digest(content.as_bytes());
+    let mut hasher = Sha256::new();
+    hasher.update(source.as_bytes());
+    hasher.update([0]);
+    hasher.update(file_path.as_bytes());
+    hasher.update([0]);
+    hasher.update(content_hash);
+    if occurrence > 0 {
+        hasher.update(occurrence.to_le_bytes());
+    }
+    hex::encode(&hasher.finalize()[..16])
+}
+
+/// Hash of a file's chunked content; changes whenever any chunk changes.
+pub fn file_hash<'a>(chunks: impl IntoIterator<Item = &'a str>) -> String {
+    let mut hasher = Sha256::new();
+    for chunk in chunks {
+        hasher.update(chunk.as_bytes());
+        hasher.update([0]);
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Schema metadata key holding the JSON `ModelFingerprint` of the table.
+const FINGERPRINT_KEY: &str = "brain.embedding";
+
+fn missing_fingerprint(table_name: &str) -> anyhow::Error {
+    anyhow::anyhow!(
+        "Table {} has no embedding fingerprint; it was built before fingerprints were recorded. \
+         Record the model it was built with using `brain migrate-embeddings --adopt-legacy <model>`, \
+         then re-embed it with `brain migrate-embeddings --model <model>`, since this version \
+         of brain embeds differently.",
+        table_name
+    )
+}
+
+/// The fingerprint of a table built before fingerprints were recorded: mean
+/// pooling without prefixes, from the model the user says built it.
+fn legacy_fingerprint(
+    schema: &Schema,
+    table_name: &str,
+    model_id: &str,
+) -> Result<ModelFingerprint> {
+    let dimensions = match schema.field_with_name("embedding")?.data_type() {
+        DataType::FixedSizeList(_, size) => *size as usize,
+        _ => anyhow::bail!("Invalid embedding column in table {}", table_name),
+    };
+    Ok(ModelFingerprint {
+        model_id: model_id.to_string(),
+        revision: "main".to_string(),
+        pooling: "mean".to_string(),
+        normalize: true,
+        dimensions,
+        document_prefix: String::new(),
+        encoder_version: 0,
+    })
+}
+
 pub struct VectorStore {
     db: lancedb::connection::Connection,
+    db_path: PathBuf,
     table_name: String,
     dimensions: usize,
+    fingerprint: ModelFingerprint,
+    index: VectorIndexConfig,
 }
 
 impl VectorStore {
-    pub async fn new(db_path: &Path, table_name: &str, dimensions: usize) -> Result<Self> {
+    pub async fn new(
+        db_path: &Path,
+        table_name: &str,
+        fingerprint: ModelFingerprint,
+        index: VectorIndexConfig,
+    ) -> Result<Self> {
         let db = connect(
             db_path
                 .to_str()
@@ -82,14 +280,90 @@ impl VectorStore {
 
         let store = Self {
             db,
+            db_path: db_path.to_path_buf(),
             table_name: table_name.to_string(),
-            dimensions,
+            dimensions: fingerprint.dimensions,
+            fingerprint,
+            index,
         };
 
         store.ensure_table().await?;
+        store.ensure_fts_index().await?;
         Ok(store)
     }
 
+    /// Opens an existing table with the fingerprint it was built with, without
+    /// checking it against the configured model. Used to read a table for migration.
+    pub async fn open_existing(
+        db_path: &Path,
+        table_name: &str,
+        index: VectorIndexConfig,
+    ) -> Result<Self> {
+        let db = connect(
+            db_path
+                .to_str()
+                .ok_or_else(|| anyhow::anyhow!("Invalid path"))?,
+        )
+        .execute()
+        .await?;
+
+        let table = db.open_table(table_name).execute().await?;
+        let schema = table.schema().await?;
+
+        let fingerprint = match schema.metadata().get(FINGERPRINT_KEY) {
+            Some(json) => serde_json::from_str::<ModelFingerprint>(json)?,
+            None => return Err(missing_fingerprint(table_name)),
+        };
+
+        Ok(Self {
+            db,
+            db_path: db_path.to_path_buf(),
+            table_name: table_name.to_string(),
+            dimensions: fingerprint.dimensions,
+            fingerprint,
+            index,
+        })
+    }
+
+    /// Records the legacy fingerprint of `model_id` on a table that has none.
+    /// Only run on request, since the model cannot be read back from the vectors.
+    pub async fn adopt_legacy(
+        db_path: &Path,
+        table_name: &str,
+        model_id: &str,
+    ) -> Result<ModelFingerprint> {
+        let db = connect(
+            db_path
+                .to_str()
+                .ok_or_else(|| anyhow::anyhow!("Invalid path"))?,
+        )
+        .execute()
+        .await?;
+
+        let table = db.open_table(table_name).execute().await?;
+        let schema = table.schema().await?;
+        if let Some(json) = schema.metadata().get(FINGERPRINT_KEY) {
+            let stored = serde_json::from_str::<ModelFingerprint>(json)?;
+            anyhow::bail!(
+                "Table {} already records its embedding model ({})",
+                table_name,
+                stored.model_id
+            );
+        }
+
+        let legacy = legacy_fingerprint(&schema, table_name, model_id)?;
+        table
+            .as_native()
+            .ok_or_else(|| anyhow::anyhow!("Table {} is not local", table_name))?
+            .replace_schema_metadata([(
+                FINGERPRINT_KEY.to_string(),
+                serde_json::to_string(&legacy)?,
+            )])
+            .await?;
+
+        Ok(legacy)
+    }
+
     fn schema(&self) -> Arc<Schema> {
         Arc::new(Schema::new(vec![
             Field::new("id", DataType::Utf8, false),
@@ -107,13 +381,24 @@ impl VectorStore {
                 ),
                 false,
             ),
+            Field::new("file_hash", DataType::Utf8, true),
+            Field::new("start_line", DataType::Int64, true),
+            Field::new("end_line", DataType::Int64, true),
+            Field::new("symbol", DataType::Utf8, true),
+            Field::new("heading", DataType::Utf8, true),
+            Field::new("start_byte", DataType::Int64, true),
+            Field::new("end_byte", DataType::Int64, true),
         ]))
     }
 
     async fn ensure_table(&self) -> Result<()> {
         let tables = self.db.table_names().execute().await?;
         if !tables.contains(&self.table_name) {
-            let schema = self.schema();
+            let metadata = HashMap::from([(
+                FINGERPRINT_KEY.to_string(),
+                serde_json::to_string(&self.fingerprint)?,
+            )]);
+            let schema = Arc::new(self.schema().as_ref().clone().with_metadata(metadata));
             let batch = RecordBatch::new_empty(schema.clone());
             let batches: Vec<Result<RecordBatch, arrow_schema::ArrowError>> = vec![Ok(batch)];
             let reader = RecordBatchIterator::new(batches.into_iter(), schema);
@@ -124,16 +409,78 @@ impl VectorStore {
                 .await?;
 
             tracing::info!("Created table: {}", self.table_name);
+        } else {
+            let table = self.db.open_table(&self.table_name).execute().await?;
+            let schema = table.schema().await?;
+
+            self.check_fingerprint(&schema)?;
+
+            for (column, default) in ADDED_COLUMNS {
+                if schema.field_with_name(column).is_ok() {
+                    continue;
+                }
+
+                table
+                    .add_columns(
+                        NewColumnTransform::SqlExpressions(vec![(
+                            column.to_string(),
+                            default.to_string(),
+                        )]),
+                        None,
+                    )
+                    .await?;
+                tracing::info!("Added {} column to table: {}", column, self.table_name);
+            }
         }
 
         Ok(())
     }
 
-    pub async fn insert(&self, documents: Vec<DocumentWithEmbedding>) -> Result<()> {
-        if documents.is_empty() {
-            return Ok(());
+    fn check_fingerprint(&self, schema: &Schema) -> Result<()> {
+        let stored = match schema.metadata().get(FINGERPRINT_KEY) {
+            Some(json) => serde_json::from_str::<ModelFingerprint>(json)?,
+            None => return Err(missing_fingerprint(&self.table_name)),
+        };
+
+        if stored.encoder_version != self.fingerprint.encoder_version {
+            // Vectors from another encoder cannot be reproduced by any setting.
+            anyhow::bail!(
+                "Table {} was embedded by encoder version {}, but this build of brain embeds \
+                 with version {}, so its vectors no longer match new queries. \
+                 Re-embed the index once after upgrading with \
+                 `brain migrate-embeddings --model {}`; it writes a new table and switches \
+                 the config to it when done.",
+                self.table_name,
+                stored.encoder_version,
+                self.fingerprint.encoder_version,
+                self.fingerprint.model_id
+            );
+        }
+
+        let differences = stored.differences(&self.fingerprint);
+        if !differences.is_empty() {
+            anyhow::bail!(
+                "Table {} was embedded with a different model configuration ({}; stored vs configured). \
+                 Mixing vector spaces would corrupt search results. \
+                 Restore the stored embedding settings (model = \"{}\", pooling = \"{}\", \
+                 normalize = {}), or re-embed the index with `brain migrate-embeddings --model {}`.",
+                self.table_name,
+                differences.join(", "),
+                stored.model_id,
+                stored.pooling,
+                stored.normalize,
+                self.fingerprint.model_id
+            );
         }
 
+        Ok(())
+    }
+
+    pub fn fingerprint(&self) -> &ModelFingerprint {
+        &self.fingerprint
+    }
+
+    fn to_batch(&self, documents: &[DocumentWithEmbedding]) -> Result<RecordBatch> {
         let schema = self.schema();
 
         let ids: StringArray =
@@ -149,6 +496,14 @@ impl VectorStore {
         let chunk_indices: Int64Array = documents.iter().map(|d| d.chunk_index).collect();
         let created_ats: StringArray =
             StringArray::from_iter_values(documents.iter().map(|d| d.created_at.as_str()));
+        let file_hashes: StringArray =
+            StringArray::from_iter_values(documents.iter().map(|d| d.file_hash.as_str()));
+        let start_lines: Int64Array = documents.iter().map(|d| d.start_line).collect();
+        let end_lines: Int64Array = documents.iter().map(|d| d.end_line).collect();
+        let start_bytes: Int64Array = documents.iter().map(|d| d.start_byte).collect();
+        let end_bytes: Int64Array = documents.iter().map(|d| d.end_byte).collect();
+        let symbols: StringArray = documents.iter().map(|d| d.symbol.as_deref()).collect();
+        let headings: StringArray = documents.iter().map(|d| d.heading.as_deref()).collect();
 
         let embeddings = FixedSizeListArray::from_iter_primitive::<Float32Type, _, _>(
             documents
@@ -168,9 +523,26 @@ impl VectorStore {
                 Arc::new(chunk_indices),
                 Arc::new(created_ats),
                 Arc::new(embeddings),
+                Arc::new(file_hashes),
+                Arc::new(start_lines),
+                Arc::new(end_lines),
+                Arc::new(symbols),
+                Arc::new(headings),
+                Arc::new(start_bytes),
+                Arc::new(end_bytes),
             ],
         )?;
 
+        Ok(batch)
+    }
+
+    pub async fn insert(&self, documents: Vec<DocumentWithEmbedding>) -> Result<()> {
+        if documents.is_empty() {
+            return Ok(());
+        }
+
+        let batch = self.to_batch(&documents)?;
+
         let table = self.db.open_table(&self.table_name).execute().await?;
         let batches: Vec<Result<RecordBatch, arrow_schema::ArrowError>> = vec![Ok(batch)];
         let reader = RecordBatchIterator::new(batches.into_iter(), self.schema());
@@ -180,19 +552,403 @@ impl VectorStore {
         Ok(())
     }
 
-    pub async fn search(&self, query_embedding: &[f32], limit: usize) -> Result<Vec<SearchResult>> {
+    pub async fn search(
+        &self,
+        query_embedding: &[f32],
+        options: &SearchOptions,
+    ) -> Result<Vec<SearchResult>> {
         let table = self.db.open_table(&self.table_name).execute().await?;
 
         let query_vec = query_embedding.to_vec();
 
-        let mut stream = table
+        let mut query = table
             .query()
+            .select(Select::columns(&RESULT_COLUMNS))
             .nearest_to(query_vec)?
-            .limit(limit)
+            .distance_type(self.distance_type())
+            .nprobes(options.nprobes.unwrap_or(self.index.nprobes))
+            .limit(options.limit);
+        if let Some(refine_factor) = options.refine_factor.or(self.index.refine_factor) {
+            query = query.refine_factor(refine_factor);
+        }
+        if let Some(predicate) = options.filter.to_predicate()? {
+            query = query.only_if(predicate);
+        }
+
+        let stream = query.execute().await?;
+
+        collect_results(stream).await
+    }
+
+    pub async fn search_fts(
+        &self,
+        query: &str,
+        options: &SearchOptions,
+    ) -> Result<Vec<SearchResult>> {
+        let table = self.db.open_table(&self.table_name).execute().await?;
+
+        let fts_query = FullTextSearchQuery::new(query.to_string()).with_columns(
+            &FTS_COLUMNS
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<_>>(),
+        )?;
+
+        let mut query = table
+            .query()
+            .full_text_search(fts_query)
+            .select(Select::columns(&RESULT_COLUMNS))
+            .limit(options.limit);
+        if let Some(predicate) = options.filter.to_predicate()? {
+            query = query.only_if(predicate);
+        }
+
+        let stream = query.execute().await?;
+        let mut results = collect_results(stream).await?;
+        normalize_similarity(&mut results, |r| r.bm25.unwrap_or(0.0));
+
+        Ok(results)
+    }
+
+    /// Fuses full-text and vector rankings with reciprocal rank fusion; results
+    /// are ranked by their fused value.
+    pub async fn search_hybrid(
+        &self,
+        query: &str,
+        query_embedding: &[f32],
+        options: &SearchOptions,
+    ) -> Result<Vec<SearchResult>> {
+        let candidates = SearchOptions {
+            limit: (options.limit * 4).max(20),
+            ..options.clone()
+        };
+        let vector_results = self.search(query_embedding, &candidates).await?;
+        let fts_results = self.search_fts(query, &candidates).await?;
+
+        let mut fused: HashMap<String, (f32, SearchResult)> = HashMap::new();
+
+        for results in [vector_results, fts_results] {
+            for (rank, result) in results.into_iter().enumerate() {
+                let rrf = 1.0 / (RRF_K + rank as f32 + 1.0);
+                match fused.get_mut(&result.id) {
+                    Some((fused_rrf, existing)) => {
+                        *fused_rrf += rrf;
+                        existing.distance = existing.distance.or(result.distance);
+                        existing.bm25 = existing.bm25.or(result.bm25);
+                    }
+                    None => {
+                        fused.insert(result.id.clone(), (rrf, result));
+                    }
+                }
+            }
+        }
+
+        let mut fused: Vec<(f32, SearchResult)> = fused.into_values().collect();
+        fused.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let mut results: Vec<SearchResult> = fused
+            .into_iter()
+            .take(options.limit)
+            .map(|(rrf, result)| SearchResult {
+                rrf: Some(rrf),
+                ..result
+            })
+            .collect();
+        normalize_similarity(&mut results, |r| r.rrf.unwrap_or(0.0));
+
+        Ok(results)
+    }
+
+    fn distance_type(&self) -> DistanceType {
+        match self.index.distance_type {
+            VectorDistance::Cosine => DistanceType::Cosine,
+            VectorDistance::Dot => DistanceType::Dot,
+        }
+    }
+
+    /// Creates missing indices and folds newly added rows into existing ones.
+    pub async fn update_indices(&self) -> Result<IndexReport> {
+        self.ensure_fts_index().await?;
+
+        let table = self.db.open_table(&self.table_name).execute().await?;
+        let rows = table.count_rows(None).await?;
+
+        match self.vector_index_name().await? {
+            None if rows >= self.index.min_rows => self.create_vector_index().await?,
+            None => tracing::info!(
+                "Skipping vector index: {} rows (minimum {})",
+                rows,
+                self.index.min_rows
+            ),
+            Some(name) => {
+                // An index trained for another metric would rank by the wrong distance.
+                if let Some(indexed) = table
+                    .index_stats(&name)
+                    .await?
+                    .and_then(|stats| stats.distance_type)
+                    && indexed != self.distance_type()
+                {
+                    tracing::info!(
+                        "Vector index uses {:?} distance but storage.index.distance_type is {:?}; rebuilding",
+                        indexed,
+                        self.index.distance_type
+                    );
+                    self.create_vector_index().await?;
+                }
+                table
+                    .optimize(OptimizeAction::Index(OptimizeOptions::default()))
+                    .await?;
+            }
+        }
+
+        self.index_report().await
+    }
+
+    /// Drops and retrains the vector index from all current rows.
+    pub async fn rebuild_vector_index(&self) -> Result<IndexReport> {
+        self.ensure_fts_index().await?;
+
+        let table = self.db.open_table(&self.table_name).execute().await?;
+        let rows = table.count_rows(None).await?;
+
+        if rows < self.index.min_rows {
+            anyhow::bail!(
+                "Not enough rows for a vector index: {} (minimum {}, see storage.index.min_rows)",
+                rows,
+                self.index.min_rows
+            );
+        }
+
+        self.create_vector_index().await?;
+        table
+            .optimize(OptimizeAction::Index(OptimizeOptions::default()))
+            .await?;
+
+        self.index_report().await
+    }
+
+    async fn create_vector_index(&self) -> Result<()> {
+        let table = self.db.open_table(&self.table_name).execute().await?;
+        let distance_type = self.distance_type();
+
+        let index = match self.index.index_type {
+            VectorIndexType::IvfPq => {
+                let mut builder = IvfPqIndexBuilder::default().distance_type(distance_type);
+                if let Some(partitions) = self.index.num_partitions {
+                    builder = builder.num_partitions(partitions);
+                }
+                if let Some(sub_vectors) = self.index.num_sub_vectors {
+                    builder = builder.num_sub_vectors(sub_vectors);
+                }
+                Index::IvfPq(builder)
+            }
+            VectorIndexType::IvfHnswPq => {
+                let mut builder = IvfHnswPqIndexBuilder::default().distance_type(distance_type);
+                if let Some(partitions) = self.index.num_partitions {
+                    builder = builder.num_partitions(partitions);
+                }
+                if let Some(sub_vectors) = self.index.num_sub_vectors {
+                    builder = builder.num_sub_vectors(sub_vectors);
+                }
+                Index::IvfHnswPq(builder)
+            }
+            VectorIndexType::IvfHnswSq => {
+                let mut builder = IvfHnswSqIndexBuilder::default().distance_type(distance_type);
+                if let Some(partitions) = self.index.num_partitions {
+                    builder = builder.num_partitions(partitions);
+                }
+                Index::IvfHnswSq(builder)
+            }
+        };
+
+        tracing::info!(
+            "Building {:?} vector index ({:?} distance)...",
+            self.index.index_type,
+            self.index.distance_type
+        );
+        table
+            .create_index(&["embedding"], index)
+            .replace(true)
             .execute()
             .await?;
+        tracing::info!("Vector index built");
 
-        let mut results = Vec::new();
+        Ok(())
+    }
+
+    async fn vector_index_name(&self) -> Result<Option<String>> {
+        let table = self.db.open_table(&self.table_name).execute().await?;
+        Ok(table
+            .list_indices()
+            .await?
+            .into_iter()
+            .find(|index| index.columns.iter().any(|c| c == "embedding"))
+            .map(|index| index.name))
+    }
+
+    pub async fn index_report(&self) -> Result<IndexReport> {
+        let table = self.db.open_table(&self.table_name).execute().await?;
+        let total_rows = table.count_rows(None).await?;
+
+        let mut report = IndexReport {
+            total_rows,
+            vector_index: None,
+            indexed_rows: 0,
+            unindexed_rows: total_rows,
+        };
+
+        if let Some(name) = self.vector_index_name().await?
+            && let Some(stats) = table.index_stats(&name).await?
+        {
+            report.vector_index = Some(format!("{} ({:?})", name, stats.index_type));
+            report.indexed_rows = stats.num_indexed_rows;
+            report.unindexed_rows = stats.num_unindexed_rows;
+        }
+
+        Ok(report)
+    }
+
+    pub async fn ensure_fts_index(&self) -> Result<()> {
+        let table = self.db.open_table(&self.table_name).execute().await?;
+
+        if table.count_rows(None).await? == 0 {
+            return Ok(());
+        }
+
+        let indices = table.list_indices().await?;
+
+        for column in FTS_COLUMNS {
+            if indices
+                .iter()
+                .any(|index| index.columns.iter().any(|c| c == column))
+            {
+                continue;
+            }
+
+            tracing::info!("Creating full-text index on {}", column);
+            table
+                .create_index(&[column], Index::FTS(FtsIndexBuilder::default()))
+                .execute()
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Upserts `documents` by id and deletes the remaining rows of `file_paths`.
+    pub async fn replace_files(
+        &self,
+        source: &str,
+        file_paths: &[String],
+        documents: Vec<DocumentWithEmbedding>,
+    ) -> Result<()> {
+        if file_paths.is_empty() {
+            return Ok(());
+        }
+        if documents.is_empty() {
+            return self.delete_files(source, file_paths).await;
+        }
+
+        let batch = self.to_batch(&documents)?;
+        let table = self.db.open_table(&self.table_name).execute().await?;
+        let batches: Vec<Result<RecordBatch, arrow_schema::ArrowError>> = vec![Ok(batch)];
+        let reader = RecordBatchIterator::new(batches.into_iter(), self.schema());
+
+        let mut merge = table.merge_insert(&["id"]);
+        merge
+            .when_matched_update_all(None)
+            .when_not_matched_insert_all()
+            .when_not_matched_by_source_delete(Some(files_predicate(source, file_paths)));
+        merge.execute(Box::new(reader)).await?;
+
+        tracing::info!(
+            "Upserted {} documents for {} files",
+            documents.len(),
+            file_paths.len()
+        );
+        Ok(())
+    }
+
+    pub async fn delete_files(&self, source: &str, file_paths: &[String]) -> Result<()> {
+        if file_paths.is_empty() {
+            return Ok(());
+        }
+
+        let table = self.db.open_table(&self.table_name).execute().await?;
+        for paths in file_paths.chunks(500) {
+            table.delete(&files_predicate(source, paths)).await?;
+        }
+        tracing::info!("Deleted {} files from source: {}", file_paths.len(), source);
+        Ok(())
+    }
+
+    /// Returns the stored `file_hash` of every file indexed for `source`.
+    pub async fn file_hashes(&self, source: &str) -> Result<HashMap<String, String>> {
+        let table = self.db.open_table(&self.table_name).execute().await?;
+
+        let mut stream = table
+            .query()
+            .only_if(format!("source = {}", sql_string(source)))
+            .select(Select::columns(&["file_path", "file_hash"]))
+            .execute()
+            .await?;
+
+        let mut hashes = HashMap::new();
+
+        while let Some(batch) = stream.next().await {
+            let batch = batch?;
+
+            let file_paths = batch
+                .column_by_name("file_path")
+                .and_then(|c| c.as_any().downcast_ref::<StringArray>())
+                .ok_or_else(|| anyhow::anyhow!("Missing/invalid file_path column"))?;
+
+            let file_hashes = batch
+                .column_by_name("file_hash")
+                .and_then(|c| c.as_any().downcast_ref::<StringArray>())
+                .ok_or_else(|| anyhow::anyhow!("Missing/invalid file_hash column"))?;
+
+            for i in 0..batch.num_rows() {
+                let hash = if file_hashes.is_null(i) {
+                    ""
+                } else {
+                    file_hashes.value(i)
+                };
+                // A file whose rows disagree on the hash is treated as changed.
+                hashes
+                    .entry(file_paths.value(i).to_string())
+                    .and_modify(|h: &mut String| {
+                        if h.as_str() != hash {
+                            h.clear();
+                        }
+                    })
+                    .or_insert_with(|| hash.to_string());
+            }
+        }
+
+        Ok(hashes)
+    }
+
+    /// Returns the stored embeddings of one file, keyed by chunk id.
+    pub async fn file_embeddings(
+        &self,
+        source: &str,
+        file_path: &str,
+    ) -> Result<HashMap<String, Vec<f32>>> {
+        let table = self.db.open_table(&self.table_name).execute().await?;
+
+        let mut stream = table
+            .query()
+            .only_if(format!(
+                "source = {} AND file_path = {}",
+                sql_string(source),
+                sql_string(file_path)
+            ))
+            .select(Select::columns(&["id", "embedding"]))
+            .execute()
+            .await?;
+
+        let mut embeddings = HashMap::new();
 
         while let Some(batch) = stream.next().await {
             let batch = batch?;
@@ -202,40 +958,136 @@ impl VectorStore {
                 .and_then(|c| c.as_any().downcast_ref::<StringArray>())
                 .ok_or_else(|| anyhow::anyhow!("Missing/invalid id column"))?;
 
-            let contents = batch
-                .column_by_name("content")
-                .and_then(|c| c.as_any().downcast_ref::<StringArray>())
-                .ok_or_else(|| anyhow::anyhow!("Missing/invalid content column"))?;
+            let vectors = batch
+                .column_by_name("embedding")
+                .and_then(|c| c.as_any().downcast_ref::<FixedSizeListArray>())
+                .ok_or_else(|| anyhow::anyhow!("Missing/invalid embedding column"))?;
 
-            let sources = batch
-                .column_by_name("source")
-                .and_then(|c| c.as_any().downcast_ref::<StringArray>())
-                .ok_or_else(|| anyhow::anyhow!("Missing/invalid source column"))?;
+            for i in 0..batch.num_rows() {
+                let vector = vectors.value(i);
+                let values = vector
+                    .as_any()
+                    .downcast_ref::<Float32Array>()
+                    .ok_or_else(|| anyhow::anyhow!("Invalid embedding values"))?;
+                embeddings.insert(ids.value(i).to_string(), values.values().to_vec());
+            }
+        }
 
-            let file_paths = batch
-                .column_by_name("file_path")
-                .and_then(|c| c.as_any().downcast_ref::<StringArray>())
-                .ok_or_else(|| anyhow::anyhow!("Missing/invalid file_path column"))?;
+        Ok(embeddings)
+    }
+
+    pub async fn delete_by_source(&self, source: &str) -> Result<()> {
+        let table = self.db.open_table(&self.table_name).execute().await?;
+        let predicate = format!("source = {}", sql_string(source));
+        table.delete(&predicate).await?;
+        tracing::info!("Deleted documents from source: {}", source);
+        Ok(())
+    }
+
+    /// Reads stored rows without their embeddings, in table scan order.
+    pub async fn read_documents(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<DocumentWithEmbedding>> {
+        let table = self.db.open_table(&self.table_name).execute().await?;
+
+        let mut stream = table
+            .query()
+            .select(Select::columns(&[
+                "id",
+                "content",
+                "source",
+                "source_type",
+                "file_path",
+                "file_hash",
+                "chunk_index",
+                "start_line",
+                "end_line",
+                "start_byte",
+                "end_byte",
+                "symbol",
+                "heading",
+                "created_at",
+            ]))
+            .offset(offset)
+            .limit(limit)
+            .execute()
+            .await?;
+
+        let mut documents = Vec::new();
+
+        while let Some(batch) = stream.next().await {
+            let batch = batch?;
+
+            let ids = string_column(&batch, "id")?;
+            let contents = string_column(&batch, "content")?;
+            let sources = string_column(&batch, "source")?;
+            let source_types = string_column(&batch, "source_type")?;
+            let file_paths = string_column(&batch, "file_path")?;
+            let file_hashes = string_column(&batch, "file_hash")?;
+            let symbols = string_column(&batch, "symbol")?;
+            let headings = string_column(&batch, "heading")?;
+            let created_ats = string_column(&batch, "created_at")?;
+
+            let int_column = |name: &str| {
+                batch
+                    .column_by_name(name)
+                    .and_then(|c| c.as_any().downcast_ref::<Int64Array>())
+                    .ok_or_else(|| anyhow::anyhow!("Missing/invalid {} column", name))
+            };
+            let chunk_indices = int_column("chunk_index")?;
+            let start_lines = int_column("start_line")?;
+            let end_lines = int_column("end_line")?;
+            let start_bytes = int_column("start_byte")?;
+            let end_bytes = int_column("end_byte")?;
+            let optional_int =
+                |column: &Int64Array, i: usize| (!column.is_null(i)).then(|| column.value(i));
 
             for i in 0..batch.num_rows() {
-                results.push(SearchResult {
+                documents.push(DocumentWithEmbedding {
                     id: ids.value(i).to_string(),
                     content: contents.value(i).to_string(),
                     source: sources.value(i).to_string(),
+                    source_type: source_types.value(i).to_string(),
                     file_path: file_paths.value(i).to_string(),
+                    file_hash: file_hashes.value(i).to_string(),
+                    chunk_index: chunk_indices.value(i),
+                    start_line: optional_int(start_lines, i),
+                    end_line: optional_int(end_lines, i),
+                    start_byte: optional_int(start_bytes, i),
+                    end_byte: optional_int(end_bytes, i),
+                    symbol: (!symbols.is_null(i)).then(|| symbols.value(i).to_string()),
+                    heading: (!headings.is_null(i)).then(|| headings.value(i).to_string()),
+                    created_at: created_ats.value(i).to_string(),
+                    embedding: Vec::new(),
                 });
             }
         }
 
-        Ok(results)
+        Ok(documents)
     }
 
-    pub async fn delete_by_source(&self, source: &str) -> Result<()> {
+    pub async fn ids(&self) -> Result<HashSet<String>> {
         let table = self.db.open_table(&self.table_name).execute().await?;
-        let predicate = format!("source = '{}'", source);
-        table.delete(&predicate).await?;
-        tracing::info!("Deleted documents from source: {}", source);
-        Ok(())
+
+        let mut stream = table
+            .query()
+            .select(Select::columns(&["id"]))
+            .execute()
+            .await?;
+
+        let mut ids = HashSet::new();
+
+        while let Some(batch) = stream.next().await {
+            let batch = batch?;
+            let arr = string_column(&batch, "id")?;
+            for i in 0..arr.len() {
+                ids.insert(arr.value(i).to_string());
+            }
+        }
+
+        Ok(ids)
     }
 
     pub async fn list_sources(&self) -> Result<Vec<String>> {
@@ -243,7 +1095,7 @@ impl VectorStore {
 
         let mut stream = table
             .query()
-            .select(lancedb::query::Select::columns(&["source"]))
+            .select(Select::columns(&["source"]))
             .execute()
             .await?;
 
@@ -269,10 +1121,172 @@ impl VectorStore {
     }
 }
 
+async fn collect_results(mut stream: SendableRecordBatchStream) -> Result<Vec<SearchResult>> {
+    let mut results = Vec::new();
+
+    while let Some(batch) = stream.next().await {
+        let batch = batch?;
+
+        let ids = string_column(&batch, "id")?;
+        let contents = string_column(&batch, "content")?;
+        let sources = string_column(&batch, "source")?;
+        let source_types = string_column(&batch, "source_type")?;
+        let file_paths = string_column(&batch, "file_path")?;
+        let created_ats = string_column(&batch, "created_at")?;
+        let optional_string = |name: &str| {
+            batch
+                .column_by_name(name)
+                .and_then(|c| c.as_any().downcast_ref::<StringArray>())
+        };
+        let symbols = optional_string("symbol");
+        let headings = optional_string("heading");
+
+        let chunk_indices = batch
+            .column_by_name("chunk_index")
+            .and_then(|c| c.as_any().downcast_ref::<Int64Array>())
+            .ok_or_else(|| anyhow::anyhow!("Missing/invalid chunk_index column"))?;
+
+        let int_column = |name: &str| {
+            batch
+                .column_by_name(name)
+                .and_then(|c| c.as_any().downcast_ref::<Int64Array>())
+        };
+        let start_lines = int_column("start_line");
+        let end_lines = int_column("end_line");
+        let start_bytes = int_column("start_byte");
+        let end_bytes = int_column("end_byte");
+
+        let float_column = |name: &str| {
+            batch
+                .column_by_name(name)
+                .and_then(|c| c.as_any().downcast_ref::<Float32Array>())
+        };
+        let distances = float_column("_distance");
+        let bm25_scores = float_column("_score");
+
+        let optional_int = |column: Option<&Int64Array>, i: usize| {
+            column
+                .filter(|c| !c.is_null(i))
+                .map(|c| c.value(i) as usize)
+        };
+        let optional_float = |column: Option<&Float32Array>, i: usize| {
+            column.filter(|c| !c.is_null(i)).map(|c| c.value(i))
+        };
+
+        for i in 0..batch.num_rows() {
+            let distance = optional_float(distances, i);
+            let bm25 = optional_float(bm25_scores, i);
+
+            results.push(SearchResult {
+                id: ids.value(i).to_string(),
+                content: contents.value(i).to_string(),
+                source: sources.value(i).to_string(),
+                source_type: source_types.value(i).to_string(),
+                file_path: file_paths.value(i).to_string(),
+                chunk_index: chunk_indices.value(i) as usize,
+                start_line: optional_int(start_lines, i),
+                end_line: optional_int(end_lines, i),
+                start_byte: optional_int(start_bytes, i),
+                end_byte: optional_int(end_bytes, i),
+                symbol: symbols
+                    .filter(|c| !c.is_null(i))
+                    .map(|c| c.value(i).to_string()),
+                heading: headings
+                    .filter(|c| !c.is_null(i))
+                    .map(|c| c.value(i).to_string()),
+                created_at: created_ats.value(i).to_string(),
+                distance,
+                bm25,
+                rrf: None,
+                similarity: distance.map(|d| (1.0 - d).clamp(0.0, 1.0)).unwrap_or(0.0),
+            });
+        }
+    }
+
+    Ok(results)
+}
+
+/// Min-max scales a raw per-query score into `similarity`, so the best result
+/// scores 1 and the worst 0. A single result, or a tie, scores 1.
+fn normalize_similarity(results: &mut [SearchResult], raw: impl Fn(&SearchResult) -> f32) {
+    let (min, max) = results
+        .iter()
+        .map(&raw)
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), v| {
+            (min.min(v), max.max(v))
+        });
+    for result in results.iter_mut() {
+        result.similarity = if max > min {
+            (raw(result) - min) / (max - min)
+        } else {
+            1.0
+        };
+    }
+}
+
+fn string_column<'a>(batch: &'a RecordBatch, name: &str) -> Result<&'a StringArray> {
+    batch
+        .column_by_name(name)
+        .and_then(|c| c.as_any().downcast_ref::<StringArray>())
+        .ok_or_else(|| anyhow::anyhow!("Missing/invalid {} column", name))
+}
+
+fn files_predicate(source: &str, file_paths: &[String]) -> String {
+    let paths = file_paths
+        .iter()
+        .map(|p| sql_string(p))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(
+        "source = {} AND file_path IN ({})",
+        sql_string(source),
+        paths
+    )
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SearchResult {
     pub id: String,
     pub content: String,
     pub source: String,
+    pub source_type: String,
     pub file_path: String,
+    pub chunk_index: usize,
+    pub start_line: Option<usize>,
+    pub end_line: Option<usize>,
+    /// Byte range of `content` in the file, for chunks indexed with offsets.
+    pub start_byte: Option<usize>,
+    pub end_byte: Option<usize>,
+    /// Code item the chunk holds, for chunks of parsed source files.
+    pub symbol: Option<String>,
+    /// Heading path of the section, for chunks of Markdown, RST and AsciiDoc files.
+    pub heading: Option<String>,
+    pub created_at: String,
+    /// Vector distance (cosine or dot, see `storage.index`), for vector hits.
+    pub distance: Option<f32>,
+    /// BM25 score, for results found by full-text search.
+    pub bm25: Option<f32>,
+    /// Reciprocal rank fusion sum, for hybrid results.
+    pub rrf: Option<f32>,
+    /// Similarity in [0, 1] in every mode: `1 - distance` clamped for vector
+    /// search, BM25 or RRF min-max normalized within the query for fts and
+    /// hybrid. `min_score` is compared against it.
+    pub similarity: f32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexReport {
+    pub total_rows: usize,
+    pub vector_index: Option<String>,
+    pub indexed_rows: usize,
+    pub unindexed_rows: usize,
+}
+
+impl SearchResult {
+    pub fn location(&self) -> String {
+        match (self.start_line, self.end_line) {
+            (Some(start), Some(end)) => format!("{}:{}-{}", self.file_path, start, end),
+            _ => self.file_path.clone(),
+        }
+    }
 }